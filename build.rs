@@ -3,16 +3,170 @@ use std::fs::File;
 use std::io::Write;
 use std::path::PathBuf;
 
+/// Flash geometry comes from `tomu-image`, so host tools, `toboot_config!`
+/// and `tomu::layout` all agree on it.
+const FLASH_SIZE: u32 = tomu_image::FLASH_SIZE as u32;
+const PAGE_SIZE: u32 = tomu_image::PAGE_SIZE as u32;
+const TOBOOT_SECTORS: u32 = tomu_image::TOBOOT_SECTORS as u32;
+
+/// Pages reserved for persistent data when `TOMU_PERSISTENT_PAGES` is not set.
+const DEFAULT_PERSISTENT_PAGES: u32 = 2;
+
+struct Layout {
+    bootloader: bool,
+    /// First flash page of the application
+    start_page: u32,
+    /// First flash page of persistent data region, up to the end of flash
+    persistent_page: u32,
+}
+
+fn env_u32(name: &str) -> Option<u32> {
+    println!("cargo:rerun-if-env-changed={}", name);
+
+    let value = env::var(name).ok()?;
+    let parsed = match value.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(hex, 16),
+        None => value.parse(),
+    };
+
+    match parsed {
+        Ok(value) => Some(value),
+        Err(_) => panic!("{} must be a number, got `{}`", name, value),
+    }
+}
+
+fn layout() -> Layout {
+    let bootloader = env::var_os("CARGO_FEATURE_NO_BOOTLOADER").is_none();
+    let pages = FLASH_SIZE / PAGE_SIZE;
+
+    let start_page = match env_u32("TOMU_START_PAGE") {
+        Some(_) if !bootloader => {
+            panic!("TOMU_START_PAGE has no effect with `no-bootloader`, application starts at page 0")
+        }
+        Some(page) if page < TOBOOT_SECTORS || page >= pages => panic!(
+            "TOMU_START_PAGE must be within {}-{}, pages 0-{} are owned by toboot",
+            TOBOOT_SECTORS,
+            pages - 1,
+            TOBOOT_SECTORS - 1
+        ),
+        Some(page) => page,
+        None if bootloader => TOBOOT_SECTORS,
+        None => 0,
+    };
+
+    let persistent_pages = if env::var_os("CARGO_FEATURE_PERSISTENT_DATA").is_some() {
+        env_u32("TOMU_PERSISTENT_PAGES").unwrap_or(DEFAULT_PERSISTENT_PAGES)
+    } else {
+        0
+    };
+
+    if persistent_pages >= pages - start_page {
+        panic!(
+            "{} persistent data pages leave no room for the application starting at page {}",
+            persistent_pages, start_page
+        );
+    }
+
+    Layout {
+        bootloader,
+        start_page,
+        persistent_page: pages - persistent_pages,
+    }
+}
+
+fn memory_x(layout: &Layout) -> String {
+    let origin = layout.start_page * PAGE_SIZE;
+    let persistent = layout.persistent_page * PAGE_SIZE;
+
+    let mut memory = format!(
+        "MEMORY
+{{
+  /* NOTE 1 K = 1 KiBi = 1024 bytes */
+  FLASH : ORIGIN = {:#010x}, LENGTH = {:#x}
+  RAM : ORIGIN = 0x20000000, LENGTH = 8K
+}}
+
+__app_start__ = ORIGIN(FLASH);
+__app_end__   = __app_start__ + LENGTH(FLASH);
+__ram_start__ = ORIGIN(RAM);
+__ram_size__  = LENGTH(RAM);
+__ram_end__   = __ram_start__ + __ram_size__;
+
+/* Flash pages reserved for persistent data, untouched by the application image. */
+__persistent_start__ = {:#010x};
+__persistent_end__   = {:#010x};
+
+",
+        origin,
+        persistent - origin,
+        persistent,
+        FLASH_SIZE,
+    );
+
+    if layout.bootloader {
+        memory.push_str(include_str!("toboot.x"));
+    }
+
+    memory
+}
+
+fn layout_rs(layout: &Layout) -> String {
+    format!(
+        "/// efm32hg309f64 flash size, 64KiB.
+pub const FLASH_SIZE: u32 = {:#x};
+
+/// Flash page (sector) size, 1KiB.
+pub const PAGE_SIZE: u32 = {};
+
+/// Sectors below this one are owned by toboot.
+pub const TOBOOT_SECTORS: u32 = {};
+
+/// Whether the application is laid out to be started by toboot.
+pub const BOOTLOADER: bool = {};
+
+/// First flash page of the application, also used as toboot config `start`.
+pub const APP_START_PAGE: u32 = {};
+
+/// Address the application is linked at.
+pub const APP_START: u32 = {:#010x};
+
+/// Start address of flash reserved for persistent data.
+pub const PERSISTENT_START: u32 = {:#010x};
+
+/// End address (exclusive) of flash reserved for persistent data,
+/// same as `PERSISTENT_START` when no region is reserved.
+pub const PERSISTENT_END: u32 = {:#010x};
+",
+        FLASH_SIZE,
+        PAGE_SIZE,
+        TOBOOT_SECTORS,
+        layout.bootloader,
+        layout.start_page,
+        layout.start_page * PAGE_SIZE,
+        layout.persistent_page * PAGE_SIZE,
+        FLASH_SIZE,
+    )
+}
+
 fn main() {
+    let layout = layout();
+
     // Put the linker script somewhere the linker can find it
     let out = &PathBuf::from(env::var_os("OUT_DIR").unwrap());
     File::create(out.join("memory.x"))
         .unwrap()
-        .write_all(include_bytes!("memory.x"))
+        .write_all(memory_x(&layout).as_bytes())
         .unwrap();
     println!("cargo:rustc-link-search={}", out.display());
 
-    // Only re-run the build script when memory.x is changed,
+    // Flash layout constants for `tomu::layout`
+    File::create(out.join("layout.rs"))
+        .unwrap()
+        .write_all(layout_rs(&layout).as_bytes())
+        .unwrap();
+
+    // Only re-run the build script when the linker script template is changed,
     // instead of when any part of the source code changes.
-    println!("cargo:rerun-if-changed=memory.x");
+    println!("cargo:rerun-if-changed=toboot.x");
+    println!("cargo:rerun-if-changed=build.rs");
 }
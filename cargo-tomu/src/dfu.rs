@@ -0,0 +1,431 @@
+//! DFU 1.1 download client
+//!
+//! Implements the host side of DFU download (DNLOAD, GETSTATUS, and
+//! manifestation), on top of any `Transport` able to send class requests
+//! to the DFU interface. See `libusb` for real devices and `fake` for an
+//! in-memory toboot device.
+use std::{
+    error, fmt, thread,
+    time::{Duration, Instant},
+};
+
+/// DFU class requests
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Request {
+    Detach = 0,
+    Dnload = 1,
+    Upload = 2,
+    GetStatus = 3,
+    ClrStatus = 4,
+    GetState = 5,
+    Abort = 6,
+}
+
+/// Device state, as reported by DFU_GETSTATUS
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum State {
+    AppIdle = 0,
+    AppDetach = 1,
+    DfuIdle = 2,
+    DfuDnloadSync = 3,
+    DfuDnbusy = 4,
+    DfuDnloadIdle = 5,
+    DfuManifestSync = 6,
+    DfuManifest = 7,
+    DfuManifestWaitReset = 8,
+    DfuUploadIdle = 9,
+    DfuError = 10,
+}
+
+impl State {
+    pub fn from_u8(state: u8) -> Option<Self> {
+        Some(match state {
+            0 => State::AppIdle,
+            1 => State::AppDetach,
+            2 => State::DfuIdle,
+            3 => State::DfuDnloadSync,
+            4 => State::DfuDnbusy,
+            5 => State::DfuDnloadIdle,
+            6 => State::DfuManifestSync,
+            7 => State::DfuManifest,
+            8 => State::DfuManifestWaitReset,
+            9 => State::DfuUploadIdle,
+            10 => State::DfuError,
+            _ => return None,
+        })
+    }
+}
+
+/// Status code, as reported by DFU_GETSTATUS
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    Ok = 0x00,
+    ErrTarget = 0x01,
+    ErrFile = 0x02,
+    ErrWrite = 0x03,
+    ErrErase = 0x04,
+    ErrCheckErased = 0x05,
+    ErrProg = 0x06,
+    ErrVerify = 0x07,
+    ErrAddress = 0x08,
+    ErrNotdone = 0x09,
+    ErrFirmware = 0x0a,
+    ErrVendor = 0x0b,
+    ErrUsbr = 0x0c,
+    ErrPor = 0x0d,
+    ErrUnknown = 0x0e,
+    ErrStalledpkt = 0x0f,
+}
+
+impl Status {
+    pub fn from_u8(status: u8) -> Option<Self> {
+        Some(match status {
+            0x00 => Status::Ok,
+            0x01 => Status::ErrTarget,
+            0x02 => Status::ErrFile,
+            0x03 => Status::ErrWrite,
+            0x04 => Status::ErrErase,
+            0x05 => Status::ErrCheckErased,
+            0x06 => Status::ErrProg,
+            0x07 => Status::ErrVerify,
+            0x08 => Status::ErrAddress,
+            0x09 => Status::ErrNotdone,
+            0x0a => Status::ErrFirmware,
+            0x0b => Status::ErrVendor,
+            0x0c => Status::ErrUsbr,
+            0x0d => Status::ErrPor,
+            0x0e => Status::ErrUnknown,
+            0x0f => Status::ErrStalledpkt,
+            _ => return None,
+        })
+    }
+}
+
+/// Response to DFU_GETSTATUS
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GetStatus {
+    pub status: Status,
+    /// Minimum time the host should wait before the next DFU_GETSTATUS
+    pub poll_timeout: Duration,
+    pub state: State,
+}
+
+impl GetStatus {
+    /// Size of DFU_GETSTATUS response in bytes
+    pub const LENGTH: usize = 6;
+
+    pub fn from_bytes(bytes: &[u8; Self::LENGTH]) -> Option<Self> {
+        let poll_timeout = u32::from_le_bytes([bytes[1], bytes[2], bytes[3], 0]);
+
+        Some(GetStatus {
+            status: Status::from_u8(bytes[0])?,
+            poll_timeout: Duration::from_millis(poll_timeout.into()),
+            state: State::from_u8(bytes[4])?,
+        })
+    }
+
+    pub fn to_bytes(&self) -> [u8; Self::LENGTH] {
+        let poll_timeout = (self.poll_timeout.as_millis() as u32).to_le_bytes();
+
+        [
+            self.status as u8,
+            poll_timeout[0],
+            poll_timeout[1],
+            poll_timeout[2],
+            self.state as u8,
+            0,
+        ]
+    }
+}
+
+/// DFU functional descriptor
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FunctionalDescriptor {
+    /// Device can still communicate after manifestation
+    pub manifestation_tolerant: bool,
+    /// Device will detach by itself after DFU_DETACH
+    pub will_detach: bool,
+    /// Maximum time the device waits for a reset after DFU_DETACH
+    pub detach_timeout: Duration,
+    /// Maximum number of bytes per DFU_DNLOAD
+    pub transfer_size: u16,
+}
+
+impl FunctionalDescriptor {
+    /// Functional descriptor type
+    pub const DESCRIPTOR_TYPE: u8 = 0x21;
+
+    /// Parse functional descriptor out of interface `extra` descriptors.
+    pub fn find(extra: &[u8]) -> Option<Self> {
+        let mut rest = extra;
+
+        while rest.len() >= 2 {
+            let len = usize::from(rest[0]);
+            if len < 2 || len > rest.len() {
+                return None;
+            }
+
+            if rest[1] == Self::DESCRIPTOR_TYPE && len >= 7 {
+                return Some(FunctionalDescriptor {
+                    manifestation_tolerant: rest[2] & (1 << 2) != 0,
+                    will_detach: rest[2] & (1 << 3) != 0,
+                    detach_timeout: Duration::from_millis(
+                        u16::from_le_bytes([rest[3], rest[4]]).into(),
+                    ),
+                    transfer_size: u16::from_le_bytes([rest[5], rest[6]]),
+                });
+            }
+
+            rest = &rest[len..];
+        }
+
+        None
+    }
+}
+
+/// Interface protocol of applications that can detach into DFU mode
+pub const PROTOCOL_RUNTIME: u8 = 0x01;
+
+/// USB device considered by `select_device`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DeviceInfo {
+    pub vid: u16,
+    pub pid: u16,
+    /// Protocol of its first DFU interface, `None` without one
+    pub dfu_protocol: Option<u8>,
+}
+
+/// Index of the device to upload to in `devices`: the first one matching
+/// `vid`:`pid` with a DFU interface, or else, if `runtime` is set, the first
+/// one from the same vendor with a DFU run-time interface, e.g. a Tomu
+/// application. Run-time interfaces of other vendors are never picked,
+/// they'd be sent DFU_DETACH.
+pub fn select_device(devices: &[DeviceInfo], vid: u16, pid: u16, runtime: bool) -> Option<usize> {
+    let mut fallback = None;
+
+    for (index, device) in devices.iter().enumerate() {
+        match device.dfu_protocol {
+            Some(PROTOCOL_RUNTIME) if !runtime => {}
+            Some(_) if (device.vid, device.pid) == (vid, pid) => return Some(index),
+            Some(PROTOCOL_RUNTIME) if device.vid == vid && fallback.is_none() => {
+                fallback = Some(index)
+            }
+            _ => {}
+        }
+    }
+
+    fallback
+}
+
+/// Connection to a DFU interface
+pub trait Transport {
+    type Error: fmt::Display + fmt::Debug;
+
+    /// Send class request `request` to the DFU interface, with `data` as data stage.
+    fn control_out(&mut self, request: Request, value: u16, data: &[u8])
+        -> Result<(), Self::Error>;
+
+    /// Send class request `request` to the DFU interface, reading the data stage into `buf`.
+    fn control_in(
+        &mut self,
+        request: Request,
+        value: u16,
+        buf: &mut [u8],
+    ) -> Result<usize, Self::Error>;
+
+    /// DFU functional descriptor of the interface.
+    fn functional_descriptor(&self) -> FunctionalDescriptor;
+
+    /// Reset the device, and reconnect to it.
+    fn reset(&mut self) -> Result<(), Self::Error>;
+}
+
+/// DFU download error
+#[derive(Debug)]
+pub enum Error<E> {
+    /// Transport failed
+    Transport(E),
+    /// Device reported an error
+    Device { status: Status, state: State },
+    /// Device is in a state it's not supposed to be in
+    UnexpectedState(State),
+    /// Device sent malformed DFU_GETSTATUS response
+    InvalidStatus,
+    /// Device didn't reach the expected state in time
+    Timeout(State),
+}
+
+impl<E: fmt::Display> fmt::Display for Error<E> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Transport(err) => write!(f, "transport error: {}", err),
+            Error::Device { status, state } => {
+                write!(f, "device reported {:?} in state {:?}", status, state)
+            }
+            Error::UnexpectedState(state) => write!(f, "unexpected device state {:?}", state),
+            Error::InvalidStatus => write!(f, "invalid DFU_GETSTATUS response"),
+            Error::Timeout(state) => write!(f, "timed out, device stuck in state {:?}", state),
+        }
+    }
+}
+
+impl<E: fmt::Display + fmt::Debug> error::Error for Error<E> {}
+
+/// DFU download client
+pub struct Dfu<T> {
+    transport: T,
+    timeout: Duration,
+}
+
+impl<T: Transport> Dfu<T> {
+    /// Default time a single step (e.g. writing one block) may take.
+    pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
+
+    pub fn new(transport: T) -> Self {
+        Dfu {
+            transport,
+            timeout: Self::DEFAULT_TIMEOUT,
+        }
+    }
+
+    /// Set how long a single step (e.g. writing one block) may take
+    /// before giving up with `Error::Timeout`.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn transport(&mut self) -> &mut T {
+        &mut self.transport
+    }
+
+    pub fn into_transport(self) -> T {
+        self.transport
+    }
+
+    pub fn get_status(&mut self) -> Result<GetStatus, Error<T::Error>> {
+        let mut buf = [0u8; GetStatus::LENGTH];
+        let len = self
+            .transport
+            .control_in(Request::GetStatus, 0, &mut buf)
+            .map_err(Error::Transport)?;
+
+        if len != GetStatus::LENGTH {
+            return Err(Error::InvalidStatus);
+        }
+
+        GetStatus::from_bytes(&buf).ok_or(Error::InvalidStatus)
+    }
+
+    pub fn clear_status(&mut self) -> Result<(), Error<T::Error>> {
+        self.transport
+            .control_out(Request::ClrStatus, 0, &[])
+            .map_err(Error::Transport)
+    }
+
+    pub fn abort(&mut self) -> Result<(), Error<T::Error>> {
+        self.transport
+            .control_out(Request::Abort, 0, &[])
+            .map_err(Error::Transport)
+    }
+
+    /// Ask a device in run-time mode to switch to DFU mode, then reset it
+    /// and reconnect. Devices that detach by themselves have left the bus
+    /// already, the reset is then only waiting for them to come back.
+    pub fn detach(&mut self) -> Result<(), Error<T::Error>> {
+        let descriptor = self.transport.functional_descriptor();
+        let timeout = descriptor.detach_timeout.as_millis().min(u16::MAX.into()) as u16;
+
+        self.transport
+            .control_out(Request::Detach, timeout, &[])
+            .map_err(Error::Transport)?;
+
+        self.transport.reset().map_err(Error::Transport)
+    }
+
+    /// Poll DFU_GETSTATUS, honoring the device's poll timeout,
+    /// until the device leaves `busy` states.
+    fn wait_while(&mut self, busy: &[State]) -> Result<GetStatus, Error<T::Error>> {
+        let deadline = Instant::now() + self.timeout;
+
+        loop {
+            let status = self.get_status()?;
+
+            if status.status != Status::Ok {
+                return Err(Error::Device {
+                    status: status.status,
+                    state: status.state,
+                });
+            }
+
+            if !busy.contains(&status.state) {
+                return Ok(status);
+            }
+
+            if Instant::now() + status.poll_timeout > deadline {
+                return Err(Error::Timeout(status.state));
+            }
+
+            thread::sleep(status.poll_timeout);
+        }
+    }
+
+    /// Bring the device into dfuIDLE state, detaching it from run-time mode,
+    /// clearing previous errors, or aborting unfinished transfers.
+    pub fn enter_idle(&mut self) -> Result<(), Error<T::Error>> {
+        let status = self.get_status()?;
+
+        match status.state {
+            State::DfuIdle => return Ok(()),
+            State::AppIdle => self.detach()?,
+            State::DfuError => self.clear_status()?,
+            State::DfuDnloadIdle | State::DfuUploadIdle => self.abort()?,
+            state => return Err(Error::UnexpectedState(state)),
+        }
+
+        match self.get_status()?.state {
+            State::DfuIdle => Ok(()),
+            state => Err(Error::UnexpectedState(state)),
+        }
+    }
+
+    /// Download `firmware` into the device, `progress` is called with the
+    /// number of bytes written so far after every block.
+    pub fn download(
+        &mut self,
+        firmware: &[u8],
+        mut progress: impl FnMut(usize),
+    ) -> Result<(), Error<T::Error>> {
+        self.enter_idle()?;
+
+        let descriptor = self.transport.functional_descriptor();
+        let transfer_size = usize::from(descriptor.transfer_size.max(1));
+
+        let mut written = 0;
+        for (block, chunk) in firmware.chunks(transfer_size).enumerate() {
+            self.transport
+                .control_out(Request::Dnload, block as u16, chunk)
+                .map_err(Error::Transport)?;
+
+            let status = self.wait_while(&[State::DfuDnloadSync, State::DfuDnbusy])?;
+            if status.state != State::DfuDnloadIdle {
+                return Err(Error::UnexpectedState(status.state));
+            }
+
+            written += chunk.len();
+            progress(written);
+        }
+
+        // Zero length download ends the transfer and starts manifestation.
+        let block = firmware.chunks(transfer_size).count();
+        self.transport
+            .control_out(Request::Dnload, block as u16, &[])
+            .map_err(Error::Transport)?;
+
+        let status = self.wait_while(&[State::DfuManifestSync, State::DfuManifest])?;
+        match status.state {
+            State::DfuIdle | State::DfuManifestWaitReset => Ok(()),
+            state => Err(Error::UnexpectedState(state)),
+        }
+    }
+}
@@ -0,0 +1,103 @@
+//! ELF to raw binary conversion, same as `objcopy -O binary`
+use std::{error, fmt};
+
+use goblin::elf::{
+    program_header::PT_LOAD,
+    section_header::{SHF_ALLOC, SHT_NOBITS},
+    Elf,
+};
+
+/// Conversion error
+#[derive(Debug)]
+pub enum Error {
+    /// Input is not a valid ELF file
+    Parse(goblin::error::Error),
+    /// ELF has no loadable section with content
+    NoLoadableSection,
+    /// Loadable sections span more than the flash size
+    TooLarge(u64),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Parse(err) => write!(f, "failed to parse ELF: {}", err),
+            Error::NoLoadableSection => write!(f, "ELF has no loadable section"),
+            Error::TooLarge(size) => write!(
+                f,
+                "loadable sections span {:#x} bytes, more than flash size ({:#x})",
+                size,
+                tomu_image::FLASH_SIZE
+            ),
+        }
+    }
+}
+
+impl error::Error for Error {}
+
+impl From<goblin::error::Error> for Error {
+    fn from(err: goblin::error::Error) -> Self {
+        Error::Parse(err)
+    }
+}
+
+/// Extract loadable sections from `elf` into raw binary.
+///
+/// Sections are placed at their load (physical) address, so initialized
+/// `.data` ends up right after `.rodata` just like in flash. The binary starts
+/// at the lowest load address, gaps between sections are filled with zeroes.
+/// Returns the load address of the first byte along with the binary.
+pub fn to_bin(elf: &[u8]) -> Result<(u64, Vec<u8>), Error> {
+    let parsed = Elf::parse(elf)?;
+
+    // (load address, file offset, size) of every section with content in flash
+    let sections: Vec<(u64, u64, u64)> = parsed
+        .section_headers
+        .iter()
+        .filter(|sh| {
+            sh.sh_flags & u64::from(SHF_ALLOC) != 0 && sh.sh_type != SHT_NOBITS && sh.sh_size > 0
+        })
+        .map(|sh| {
+            // Load address is derived from the segment containing the section,
+            // for `.data` this is in flash while its address is in RAM.
+            let lma = parsed
+                .program_headers
+                .iter()
+                .find(|ph| {
+                    ph.p_type == PT_LOAD
+                        && sh.sh_offset >= ph.p_offset
+                        && sh.sh_offset + sh.sh_size <= ph.p_offset + ph.p_filesz
+                })
+                .map(|ph| ph.p_paddr + (sh.sh_offset - ph.p_offset))
+                .unwrap_or(sh.sh_addr);
+
+            (lma, sh.sh_offset, sh.sh_size)
+        })
+        .collect();
+
+    let start = sections
+        .iter()
+        .map(|&(lma, _, _)| lma)
+        .min()
+        .ok_or(Error::NoLoadableSection)?;
+    let end = sections
+        .iter()
+        .map(|&(lma, _, size)| lma + size)
+        .max()
+        .ok_or(Error::NoLoadableSection)?;
+
+    if end - start > tomu_image::FLASH_SIZE as u64 {
+        return Err(Error::TooLarge(end - start));
+    }
+
+    let mut bin = vec![0u8; (end - start) as usize];
+    for (lma, offset, size) in sections {
+        let dest = (lma - start) as usize;
+        let src = offset as usize;
+        let len = size as usize;
+
+        bin[dest..dest + len].copy_from_slice(&elf[src..src + len]);
+    }
+
+    Ok((start, bin))
+}
@@ -0,0 +1,338 @@
+//! In-memory toboot device
+//!
+//! Implements `dfu::Transport` by emulating toboot's DFU state machine,
+//! so downloads and their error paths can be exercised without hardware.
+//!
+//! ```
+//! use cargo_tomu::{dfu::Dfu, fake::FakeToboot};
+//!
+//! let mut dfu = Dfu::new(FakeToboot::new());
+//! dfu.download(&[0xaa; 3000], |_| {}).unwrap();
+//!
+//! assert_eq!(dfu.transport().flash(), &[0xaa; 3000][..]);
+//! ```
+use std::{fmt, time::Duration};
+
+use crate::dfu::{FunctionalDescriptor, GetStatus, Request, State, Status, Transport};
+use tomu_image::{FLASH_SIZE, PAGE_SIZE, TOBOOT_SECTORS};
+
+/// Fake transport error
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// Device stalled the request
+    Stall,
+    /// Device went away
+    Disconnected,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Stall => write!(f, "request stalled"),
+            Error::Disconnected => write!(f, "device disconnected"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Emulated toboot device
+pub struct FakeToboot {
+    state: State,
+    status: Status,
+    flash: Vec<u8>,
+    transfer_size: u16,
+    poll_timeout: Duration,
+    busy_polls: usize,
+    pending_busy: usize,
+    next_block: u16,
+    fail_at: Option<(u16, Status)>,
+    disconnect_at: Option<u16>,
+    stuck_busy: bool,
+    will_detach: bool,
+    /// Left the bus on its own, until the host reconnects with a reset
+    gone: bool,
+    resets: usize,
+}
+
+impl Default for FakeToboot {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FakeToboot {
+    /// Application space, everything above toboot sectors.
+    pub const CAPACITY: usize = FLASH_SIZE - TOBOOT_SECTORS as usize * PAGE_SIZE;
+
+    /// Device in DFU mode, idle, with erased flash.
+    pub fn new() -> Self {
+        FakeToboot {
+            state: State::DfuIdle,
+            status: Status::Ok,
+            flash: Vec::new(),
+            transfer_size: PAGE_SIZE as u16,
+            poll_timeout: Duration::from_millis(0),
+            busy_polls: 0,
+            pending_busy: 0,
+            next_block: 0,
+            fail_at: None,
+            disconnect_at: None,
+            stuck_busy: false,
+            will_detach: false,
+            gone: false,
+            resets: 0,
+        }
+    }
+
+    /// Start in run-time mode (appIDLE), expecting DFU_DETACH and a reset.
+    pub fn in_application(mut self) -> Self {
+        self.state = State::AppIdle;
+        self
+    }
+
+    /// Report bitWillDetach, and leave the bus on DFU_DETACH
+    /// without waiting for a reset.
+    pub fn will_detach(mut self) -> Self {
+        self.will_detach = true;
+        self
+    }
+
+    /// Start in dfuERROR with `status`, left over from a previous session.
+    pub fn in_error(mut self, status: Status) -> Self {
+        self.state = State::DfuError;
+        self.status = status;
+        self
+    }
+
+    /// Report dfuDNBUSY `polls` times after every block, asking the host
+    /// to wait `poll_timeout` between polls.
+    pub fn busy(mut self, polls: usize, poll_timeout: Duration) -> Self {
+        self.busy_polls = polls;
+        self.poll_timeout = poll_timeout;
+        self
+    }
+
+    /// Never finish writing, report dfuDNBUSY forever.
+    pub fn stuck_busy(mut self, poll_timeout: Duration) -> Self {
+        self.stuck_busy = true;
+        self.poll_timeout = poll_timeout;
+        self
+    }
+
+    /// Fail writing block `block` with `status`.
+    pub fn fail_at_block(mut self, block: u16, status: Status) -> Self {
+        self.fail_at = Some((block, status));
+        self
+    }
+
+    /// Drop off the bus when block `block` is sent.
+    pub fn disconnect_at_block(mut self, block: u16) -> Self {
+        self.disconnect_at = Some(block);
+        self
+    }
+
+    pub fn with_transfer_size(mut self, transfer_size: u16) -> Self {
+        self.transfer_size = transfer_size;
+        self
+    }
+
+    /// Content written so far.
+    pub fn flash(&self) -> &[u8] {
+        &self.flash
+    }
+
+    pub fn state(&self) -> State {
+        self.state
+    }
+
+    /// Number of bus resets seen.
+    pub fn resets(&self) -> usize {
+        self.resets
+    }
+
+    fn stall(&mut self) -> Result<(), Error> {
+        self.state = State::DfuError;
+        self.status = Status::ErrStalledpkt;
+        Err(Error::Stall)
+    }
+
+    fn dnload(&mut self, block: u16, data: &[u8]) -> Result<(), Error> {
+        match self.state {
+            State::DfuIdle | State::DfuDnloadIdle => {}
+            _ => return self.stall(),
+        }
+
+        if data.is_empty() {
+            // Zero length download while idle is a protocol error.
+            if self.state == State::DfuIdle {
+                return self.stall();
+            }
+
+            self.state = State::DfuManifestSync;
+            return Ok(());
+        }
+
+        if self.disconnect_at == Some(block) {
+            return Err(Error::Disconnected);
+        }
+
+        if block != self.next_block || data.len() > usize::from(self.transfer_size) {
+            return self.stall();
+        }
+
+        self.next_block = block.wrapping_add(1);
+        self.pending_busy = self.busy_polls;
+        self.state = State::DfuDnloadSync;
+
+        if let Some((at, status)) = self.fail_at {
+            if at == block {
+                self.status = status;
+                return Ok(());
+            }
+        }
+
+        let offset = usize::from(block) * usize::from(self.transfer_size);
+        if offset + data.len() > Self::CAPACITY {
+            self.status = Status::ErrAddress;
+            return Ok(());
+        }
+
+        if self.flash.len() < offset + data.len() {
+            self.flash.resize(offset + data.len(), 0xff);
+        }
+        self.flash[offset..offset + data.len()].copy_from_slice(data);
+
+        Ok(())
+    }
+
+    fn get_status(&mut self) -> GetStatus {
+        let mut poll_timeout = Duration::from_millis(0);
+
+        if self.status != Status::Ok {
+            self.state = State::DfuError;
+        } else {
+            match self.state {
+                State::DfuDnloadSync | State::DfuDnbusy => {
+                    if self.stuck_busy || self.pending_busy > 0 {
+                        self.pending_busy = self.pending_busy.saturating_sub(1);
+                        self.state = State::DfuDnbusy;
+                        poll_timeout = self.poll_timeout;
+                    } else {
+                        self.state = State::DfuDnloadIdle;
+                    }
+                }
+                State::DfuManifestSync => {
+                    self.state = State::DfuManifest;
+                    poll_timeout = self.poll_timeout;
+                }
+                State::DfuManifest => {
+                    // Manifestation tolerant, go back to idle.
+                    self.state = State::DfuIdle;
+                    self.next_block = 0;
+                }
+                _ => {}
+            }
+        }
+
+        GetStatus {
+            status: self.status,
+            poll_timeout,
+            state: self.state,
+        }
+    }
+}
+
+impl Transport for FakeToboot {
+    type Error = Error;
+
+    fn control_out(&mut self, request: Request, value: u16, data: &[u8]) -> Result<(), Error> {
+        if self.gone {
+            return Err(Error::Disconnected);
+        }
+
+        if self.state == State::AppIdle {
+            return match request {
+                Request::Detach => {
+                    self.state = State::AppDetach;
+                    self.gone = self.will_detach;
+                    Ok(())
+                }
+                _ => Err(Error::Stall),
+            };
+        }
+
+        match request {
+            Request::Dnload => self.dnload(value, data),
+            Request::ClrStatus if self.state == State::DfuError => {
+                self.state = State::DfuIdle;
+                self.status = Status::Ok;
+                self.next_block = 0;
+                Ok(())
+            }
+            Request::Abort => match self.state {
+                State::DfuIdle | State::DfuDnloadIdle | State::DfuUploadIdle => {
+                    self.state = State::DfuIdle;
+                    self.next_block = 0;
+                    Ok(())
+                }
+                _ => self.stall(),
+            },
+            _ => self.stall(),
+        }
+    }
+
+    fn control_in(
+        &mut self,
+        request: Request,
+        _value: u16,
+        buf: &mut [u8],
+    ) -> Result<usize, Error> {
+        if self.gone {
+            return Err(Error::Disconnected);
+        }
+
+        let response = match request {
+            Request::GetStatus => self.get_status().to_bytes(),
+            Request::GetState if self.state != State::AppIdle => [self.state as u8, 0, 0, 0, 0, 0],
+            _ => {
+                return if self.state == State::AppIdle {
+                    Err(Error::Stall)
+                } else {
+                    self.stall().map(|_| 0)
+                };
+            }
+        };
+
+        let len = match request {
+            Request::GetStatus => GetStatus::LENGTH,
+            _ => 1,
+        }
+        .min(buf.len());
+        buf[..len].copy_from_slice(&response[..len]);
+
+        Ok(len)
+    }
+
+    fn functional_descriptor(&self) -> FunctionalDescriptor {
+        FunctionalDescriptor {
+            manifestation_tolerant: true,
+            will_detach: self.will_detach,
+            detach_timeout: Duration::from_millis(1000),
+            transfer_size: self.transfer_size,
+        }
+    }
+
+    fn reset(&mut self) -> Result<(), Error> {
+        self.resets += 1;
+        self.gone = false;
+
+        if self.state == State::AppDetach {
+            self.state = State::DfuIdle;
+            self.status = Status::Ok;
+            self.next_block = 0;
+        }
+
+        Ok(())
+    }
+}
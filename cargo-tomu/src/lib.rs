@@ -0,0 +1,16 @@
+//! Host side tooling for tomu
+//!
+//! Convert application ELF into the raw binary toboot expects, and wrap it
+//! into DFU 1.1 file, and upload it with the built-in DFU client.
+pub mod dfu;
+pub mod elf;
+pub mod fake;
+#[cfg(feature = "libusb")]
+pub mod libusb;
+pub mod suffix;
+
+/// Tomu USB vendor id (pid.codes)
+pub const TOMU_VID: u16 = 0x1209;
+
+/// Toboot USB product id
+pub const TOBOOT_PID: u16 = 0x70b1;
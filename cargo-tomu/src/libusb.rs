@@ -0,0 +1,224 @@
+//! libusb transport
+//!
+//! Talks to a real device over libusb, through its DFU interface
+//! (class 0xfe, subclass 0x01). Either toboot itself, or an application
+//! exposing a DFU run-time interface, which is detached into toboot.
+use std::{
+    fmt, thread,
+    time::{Duration, Instant},
+};
+
+use rusb::{Device, DeviceHandle, GlobalContext};
+
+use crate::dfu::{self, DeviceInfo, FunctionalDescriptor, Request, Transport};
+
+const DFU_CLASS: u8 = 0xfe;
+const DFU_SUBCLASS: u8 = 0x01;
+
+/// Host to device, class request, to interface
+const REQUEST_TYPE_OUT: u8 = 0x21;
+/// Device to host, class request, to interface
+const REQUEST_TYPE_IN: u8 = 0xa1;
+
+/// How long to wait for the device to come back after a reset.
+const REENUMERATE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// libusb transport error
+#[derive(Debug)]
+pub enum Error {
+    Usb(rusb::Error),
+    /// No device with the given vendor and product id, nor any from the
+    /// same vendor with a DFU run-time interface
+    NotFound {
+        vid: u16,
+        pid: u16,
+    },
+    /// Device doesn't have a DFU interface
+    NoDfuInterface,
+}
+
+impl From<rusb::Error> for Error {
+    fn from(err: rusb::Error) -> Self {
+        Error::Usb(err)
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Usb(err) => write!(f, "{}", err),
+            Error::NotFound { vid, pid } => {
+                write!(f, "no device found with id {:04x}:{:04x}", vid, pid)
+            }
+            Error::NoDfuInterface => write!(f, "device has no DFU interface"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// DFU interface of a device opened with libusb
+pub struct LibUsb {
+    handle: DeviceHandle<GlobalContext>,
+    vid: u16,
+    pid: u16,
+    interface: u8,
+    descriptor: FunctionalDescriptor,
+    timeout: Duration,
+}
+
+impl LibUsb {
+    /// Open the first device matching `vid`:`pid` with a DFU interface, or
+    /// else the first one from vendor `vid` with a DFU run-time interface,
+    /// and claim its DFU interface. See `dfu::select_device`.
+    pub fn open(vid: u16, pid: u16) -> Result<Self, Error> {
+        Self::open_with(vid, pid, true)
+    }
+
+    fn open_with(vid: u16, pid: u16, runtime: bool) -> Result<Self, Error> {
+        let device = Self::find_device(vid, pid, runtime)?.ok_or(Error::NotFound { vid, pid })?;
+        let handle = device.open()?;
+        let (interface, setting, descriptor) = Self::find_interface(&handle)?;
+
+        // Not supported on every platform, claiming will fail if it matters.
+        let _ = handle.set_auto_detach_kernel_driver(true);
+        handle.claim_interface(interface)?;
+        if setting != 0 {
+            handle.set_alternate_setting(interface, setting)?;
+        }
+
+        Ok(LibUsb {
+            handle,
+            vid,
+            pid,
+            interface,
+            descriptor,
+            timeout: Duration::from_secs(1),
+        })
+    }
+
+    /// Set timeout of a single control transfer.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Find the device to open, see `open`. Devices in run-time mode are
+    /// skipped unless `runtime` is set.
+    fn find_device(
+        vid: u16,
+        pid: u16,
+        runtime: bool,
+    ) -> Result<Option<Device<GlobalContext>>, Error> {
+        let (mut devices, infos): (Vec<_>, Vec<_>) = rusb::devices()?
+            .iter()
+            .filter_map(|device| {
+                let descriptor = device.device_descriptor().ok()?;
+                let info = DeviceInfo {
+                    vid: descriptor.vendor_id(),
+                    pid: descriptor.product_id(),
+                    dfu_protocol: Self::dfu_protocol(&device),
+                };
+                Some((device, info))
+            })
+            .unzip();
+
+        Ok(dfu::select_device(&infos, vid, pid, runtime).map(|index| devices.swap_remove(index)))
+    }
+
+    /// Protocol of the first DFU interface of `device`, if any.
+    fn dfu_protocol(device: &Device<GlobalContext>) -> Option<u8> {
+        let config = device.active_config_descriptor().ok()?;
+
+        config.interfaces().find_map(|interface| {
+            interface
+                .descriptors()
+                .find(|setting| {
+                    setting.class_code() == DFU_CLASS && setting.sub_class_code() == DFU_SUBCLASS
+                })
+                .map(|setting| setting.protocol_code())
+        })
+    }
+
+    fn find_interface(
+        handle: &DeviceHandle<GlobalContext>,
+    ) -> Result<(u8, u8, FunctionalDescriptor), Error> {
+        let config = handle.device().active_config_descriptor()?;
+
+        for interface in config.interfaces() {
+            for setting in interface.descriptors() {
+                if setting.class_code() != DFU_CLASS || setting.sub_class_code() != DFU_SUBCLASS {
+                    continue;
+                }
+
+                // Some devices put the functional descriptor after the configuration.
+                let descriptor = FunctionalDescriptor::find(setting.extra())
+                    .or_else(|| FunctionalDescriptor::find(config.extra()))
+                    .ok_or(Error::NoDfuInterface)?;
+
+                return Ok((
+                    setting.interface_number(),
+                    setting.setting_number(),
+                    descriptor,
+                ));
+            }
+        }
+
+        Err(Error::NoDfuInterface)
+    }
+}
+
+impl Transport for LibUsb {
+    type Error = Error;
+
+    fn control_out(&mut self, request: Request, value: u16, data: &[u8]) -> Result<(), Error> {
+        self.handle.write_control(
+            REQUEST_TYPE_OUT,
+            request as u8,
+            value,
+            self.interface.into(),
+            data,
+            self.timeout,
+        )?;
+
+        Ok(())
+    }
+
+    fn control_in(&mut self, request: Request, value: u16, buf: &mut [u8]) -> Result<usize, Error> {
+        Ok(self.handle.read_control(
+            REQUEST_TYPE_IN,
+            request as u8,
+            value,
+            self.interface.into(),
+            buf,
+            self.timeout,
+        )?)
+    }
+
+    fn functional_descriptor(&self) -> FunctionalDescriptor {
+        self.descriptor
+    }
+
+    /// Reset the device, then reopen it once it's enumerated again,
+    /// as DFU mode may come with a new set of descriptors, and a new id.
+    /// Run-time interfaces aren't considered, the application that was
+    /// just detached may not have disappeared yet.
+    fn reset(&mut self) -> Result<(), Error> {
+        match self.handle.reset() {
+            Ok(()) | Err(rusb::Error::NotFound) | Err(rusb::Error::NoDevice) => {}
+            Err(err) => return Err(err.into()),
+        }
+
+        let deadline = Instant::now() + REENUMERATE_TIMEOUT;
+        loop {
+            match Self::open_with(self.vid, self.pid, false) {
+                Ok(reopened) => {
+                    *self = reopened.with_timeout(self.timeout);
+                    return Ok(());
+                }
+                Err(err) if Instant::now() > deadline => return Err(err),
+                Err(_) => thread::sleep(Duration::from_millis(100)),
+            }
+        }
+    }
+}
@@ -0,0 +1,180 @@
+//! `cargo tomu`, convert and upload tomu applications
+//!
+//! ```text
+//! cargo tomu bin <elf> [-o <output>]     convert ELF into raw binary
+//! cargo tomu dfu <elf> [-o <output>]     convert ELF into DFU file
+//! cargo tomu upload <elf>                convert ELF and upload it over DFU
+//! ```
+//!
+//! When used as cargo runner, it's invoked with the ELF path only,
+//! which behaves as `upload`.
+use std::{
+    env,
+    error::Error,
+    fs,
+    path::{Path, PathBuf},
+    process,
+};
+
+use cargo_tomu::{elf, suffix, TOBOOT_PID, TOMU_VID};
+use tomu_image::{Image, PAGE_SIZE};
+
+const USAGE: &str = "\
+usage:
+    cargo tomu bin <elf> [-o <output>]
+    cargo tomu dfu <elf> [-o <output>] [--no-verify]
+    cargo tomu upload <elf> [--no-verify]
+    cargo-tomu <elf>";
+
+enum Cmd {
+    Bin,
+    Dfu,
+    Upload,
+}
+
+struct Args {
+    cmd: Cmd,
+    elf: PathBuf,
+    output: Option<PathBuf>,
+    verify: bool,
+}
+
+fn parse_args() -> Result<Args, String> {
+    let mut args = env::args().skip(1).peekable();
+
+    // Invoked as `cargo tomu ...`
+    if args.peek().map(String::as_str) == Some("tomu") {
+        args.next();
+    }
+
+    let cmd = match args.peek().map(String::as_str) {
+        Some("bin") => Cmd::Bin,
+        Some("dfu") => Cmd::Dfu,
+        Some("upload") => Cmd::Upload,
+        Some(_) => {
+            // runner mode, first argument is the ELF itself
+            return parse_rest(Cmd::Upload, args);
+        }
+        None => return Err(USAGE.into()),
+    };
+    args.next();
+
+    parse_rest(cmd, args)
+}
+
+fn parse_rest(cmd: Cmd, args: impl Iterator<Item = String>) -> Result<Args, String> {
+    let mut args = args;
+    let mut elf = None;
+    let mut output = None;
+    let mut verify = true;
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-o" | "--output" => {
+                output = Some(args.next().ok_or("missing value for `-o`")?.into());
+            }
+            "--no-verify" => verify = false,
+            "-h" | "--help" => return Err(USAGE.into()),
+            _ if elf.is_none() => elf = Some(arg.into()),
+            _ => return Err(format!("unexpected argument `{}`\n{}", arg, USAGE)),
+        }
+    }
+
+    Ok(Args {
+        cmd,
+        elf: elf.ok_or(USAGE)?,
+        output,
+        verify,
+    })
+}
+
+/// Check toboot config header of the image before it's sent to the device.
+fn verify(load_address: u64, bin: &[u8]) -> Result<(), Box<dyn Error>> {
+    let image = Image::from_bin(bin.to_vec())?;
+    image.validate()?;
+
+    let start = u64::from(image.config().start) * PAGE_SIZE as u64;
+    if start != load_address {
+        return Err(format!(
+            "image is linked at {:#x}, but toboot config start page says {:#x}",
+            load_address, start
+        )
+        .into());
+    }
+
+    Ok(())
+}
+
+fn with_extension(path: &Path, ext: &str) -> PathBuf {
+    let mut path = path.as_os_str().to_owned();
+    path.push(".");
+    path.push(ext);
+    path.into()
+}
+
+fn run(args: Args) -> Result<(), Box<dyn Error>> {
+    let (load_address, bin) = elf::to_bin(&fs::read(&args.elf)?)?;
+
+    if args.verify && !matches!(args.cmd, Cmd::Bin) {
+        verify(load_address, &bin)?;
+    }
+
+    if let Cmd::Bin = args.cmd {
+        let bin_path = args
+            .output
+            .unwrap_or_else(|| with_extension(&args.elf, "bin"));
+        fs::write(bin_path, &bin)?;
+
+        return Ok(());
+    }
+
+    let dfu_path = args
+        .output
+        .clone()
+        .unwrap_or_else(|| with_extension(&args.elf, "dfu"));
+    fs::write(&dfu_path, suffix::append(&bin, TOMU_VID, TOBOOT_PID))?;
+
+    if let Cmd::Dfu = args.cmd {
+        return Ok(());
+    }
+
+    // Keep the raw binary around too, same as upload.sh used to.
+    fs::write(with_extension(&args.elf, "bin"), &bin)?;
+
+    upload(&bin)
+}
+
+#[cfg(feature = "libusb")]
+fn upload(bin: &[u8]) -> Result<(), Box<dyn Error>> {
+    use cargo_tomu::{dfu::Dfu, libusb::LibUsb};
+    use std::io::{self, Write};
+
+    let mut dfu = Dfu::new(LibUsb::open(TOMU_VID, TOBOOT_PID)?);
+    dfu.download(bin, |written| {
+        eprint!("\rdownloading {}/{} bytes", written, bin.len());
+        let _ = io::stderr().flush();
+    })?;
+    eprintln!();
+
+    Ok(())
+}
+
+#[cfg(not(feature = "libusb"))]
+fn upload(_: &[u8]) -> Result<(), Box<dyn Error>> {
+    Err("built without `libusb` feature, use the generated .dfu file with dfu-util".into())
+}
+
+fn main() {
+    let args = match parse_args() {
+        Ok(args) => args,
+        Err(usage) => {
+            eprintln!("{}", usage);
+            process::exit(2);
+        }
+    };
+
+    if let Err(err) = run(args) {
+        eprintln!("error: {}", err);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,54 @@
+//! DFU 1.1 file suffix, same as `dfu-suffix -v <vid> -p <pid> -a`
+
+/// Size of DFU 1.1 suffix in bytes
+pub const SUFFIX_LENGTH: usize = 16;
+
+/// DFU specification release, 1.1 is encoded as 0x0100 (sic)
+const BCD_DFU: u16 = 0x0100;
+
+/// Any device release number
+const BCD_DEVICE_ANY: u16 = 0xffff;
+
+/// CRC as stored in DFU suffix, CRC-32 (IEEE) without the final inversion.
+pub fn crc(data: &[u8]) -> u32 {
+    !crc32fast::hash(data)
+}
+
+/// Append DFU 1.1 suffix for device `vid`:`pid` to `bin`.
+pub fn append(bin: &[u8], vid: u16, pid: u16) -> Vec<u8> {
+    let mut dfu = Vec::with_capacity(bin.len() + SUFFIX_LENGTH);
+
+    dfu.extend_from_slice(bin);
+    dfu.extend_from_slice(&BCD_DEVICE_ANY.to_le_bytes());
+    dfu.extend_from_slice(&pid.to_le_bytes());
+    dfu.extend_from_slice(&vid.to_le_bytes());
+    dfu.extend_from_slice(&BCD_DFU.to_le_bytes());
+    dfu.extend_from_slice(b"UFD");
+    dfu.push(SUFFIX_LENGTH as u8);
+
+    let crc = crc(&dfu);
+    dfu.extend_from_slice(&crc.to_le_bytes());
+
+    dfu
+}
+
+/// Strip and check DFU suffix, returns the firmware along with vid and pid.
+pub fn strip(dfu: &[u8]) -> Option<(&[u8], u16, u16)> {
+    if dfu.len() < SUFFIX_LENGTH {
+        return None;
+    }
+
+    let (data, suffix) = dfu.split_at(dfu.len() - SUFFIX_LENGTH);
+    let u16_at = |offset: usize| u16::from_le_bytes([suffix[offset], suffix[offset + 1]]);
+
+    if &suffix[8..11] != b"UFD" || usize::from(suffix[11]) != SUFFIX_LENGTH {
+        return None;
+    }
+
+    let stored_crc = u32::from_le_bytes([suffix[12], suffix[13], suffix[14], suffix[15]]);
+    if stored_crc != crc(&dfu[..dfu.len() - 4]) {
+        return None;
+    }
+
+    Some((data, u16_at(4), u16_at(2)))
+}
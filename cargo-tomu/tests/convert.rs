@@ -0,0 +1,56 @@
+use cargo_tomu::{elf, suffix, TOBOOT_PID, TOMU_VID};
+
+const APP_ELF: &[u8] = include_bytes!("fixtures/app.elf");
+const APP_BIN: &[u8] = include_bytes!("fixtures/app.bin");
+const APP_DFU: &[u8] = include_bytes!("fixtures/app.dfu");
+
+#[test]
+fn elf_to_bin_matches_objcopy() {
+    let (load_address, bin) = elf::to_bin(APP_ELF).unwrap();
+
+    assert_eq!(load_address, 0x4000);
+    assert_eq!(bin, APP_BIN);
+}
+
+#[test]
+fn data_is_placed_at_load_address() {
+    let (_, bin) = elf::to_bin(APP_ELF).unwrap();
+
+    assert_eq!(&bin[bin.len() - 4..], &0xdeadbeefu32.to_le_bytes());
+}
+
+#[test]
+fn dfu_suffix_matches_dfu_suffix_tool() {
+    assert_eq!(suffix::append(APP_BIN, TOMU_VID, TOBOOT_PID), APP_DFU);
+}
+
+#[test]
+fn dfu_suffix_roundtrip() {
+    let (bin, vid, pid) = suffix::strip(APP_DFU).unwrap();
+
+    assert_eq!(bin, APP_BIN);
+    assert_eq!((vid, pid), (TOMU_VID, TOBOOT_PID));
+}
+
+#[test]
+fn dfu_suffix_rejects_bad_crc() {
+    let mut dfu = APP_DFU.to_vec();
+    dfu[0] ^= 0xff;
+
+    assert!(suffix::strip(&dfu).is_none());
+}
+
+#[test]
+fn toboot_header_is_valid() {
+    let (_, bin) = elf::to_bin(APP_ELF).unwrap();
+    let image = tomu_image::Image::from_bin(bin).unwrap();
+
+    image.validate().unwrap();
+    assert!(image.config().autorun_enabled());
+    assert_eq!(image.config().erase_mask(), 1 << 40);
+}
+
+#[test]
+fn rejects_non_elf() {
+    assert!(elf::to_bin(APP_BIN).is_err());
+}
@@ -0,0 +1,230 @@
+use std::time::Duration;
+
+use cargo_tomu::{
+    dfu::{
+        self, DeviceInfo, Dfu, Error, FunctionalDescriptor, GetStatus, State, Status,
+        PROTOCOL_RUNTIME,
+    },
+    fake::{self, FakeToboot},
+    TOBOOT_PID, TOMU_VID,
+};
+
+/// DFU mode interface protocol
+const PROTOCOL_DFU: u8 = 0x02;
+
+fn firmware(len: usize) -> Vec<u8> {
+    (0..len).map(|i| i as u8).collect()
+}
+
+#[test]
+fn download() {
+    let bin = firmware(5000);
+    let mut progress = Vec::new();
+
+    let mut dfu = Dfu::new(FakeToboot::new());
+    dfu.download(&bin, |written| progress.push(written))
+        .unwrap();
+
+    assert_eq!(dfu.transport().flash(), &bin[..]);
+    assert_eq!(dfu.transport().state(), State::DfuIdle);
+    assert_eq!(progress, [1024, 2048, 3072, 4096, 5000]);
+}
+
+#[test]
+fn download_uses_transfer_size() {
+    let bin = firmware(1000);
+    let mut progress = Vec::new();
+
+    let mut dfu = Dfu::new(FakeToboot::new().with_transfer_size(256));
+    dfu.download(&bin, |written| progress.push(written))
+        .unwrap();
+
+    assert_eq!(dfu.transport().flash(), &bin[..]);
+    assert_eq!(progress, [256, 512, 768, 1000]);
+}
+
+#[test]
+fn download_waits_while_busy() {
+    let bin = firmware(2048);
+
+    let mut dfu = Dfu::new(FakeToboot::new().busy(3, Duration::from_millis(1)));
+    dfu.download(&bin, |_| {}).unwrap();
+
+    assert_eq!(dfu.transport().flash(), &bin[..]);
+}
+
+#[test]
+fn download_detaches_application() {
+    let bin = firmware(100);
+
+    let mut dfu = Dfu::new(FakeToboot::new().in_application());
+    dfu.download(&bin, |_| {}).unwrap();
+
+    assert_eq!(dfu.transport().resets(), 1);
+    assert_eq!(dfu.transport().flash(), &bin[..]);
+}
+
+#[test]
+fn download_reconnects_after_will_detach() {
+    let bin = firmware(100);
+
+    let mut dfu = Dfu::new(FakeToboot::new().in_application().will_detach());
+    dfu.download(&bin, |_| {}).unwrap();
+
+    assert_eq!(dfu.transport().resets(), 1);
+    assert_eq!(dfu.transport().flash(), &bin[..]);
+}
+
+#[test]
+fn download_clears_previous_error() {
+    let bin = firmware(100);
+
+    let mut dfu = Dfu::new(FakeToboot::new().in_error(Status::ErrWrite));
+    dfu.download(&bin, |_| {}).unwrap();
+
+    assert_eq!(dfu.transport().flash(), &bin[..]);
+}
+
+#[test]
+fn device_error() {
+    let mut dfu = Dfu::new(FakeToboot::new().fail_at_block(2, Status::ErrProg));
+
+    match dfu.download(&firmware(4096), |_| {}) {
+        Err(Error::Device { status, state }) => {
+            assert_eq!(status, Status::ErrProg);
+            assert_eq!(state, State::DfuError);
+        }
+        other => panic!("unexpected result {:?}", other),
+    }
+
+    // Blocks before the failing one made it.
+    assert_eq!(dfu.transport().flash(), &firmware(2048)[..]);
+}
+
+#[test]
+fn too_large() {
+    let mut dfu = Dfu::new(FakeToboot::new());
+
+    match dfu.download(&firmware(FakeToboot::CAPACITY + 1), |_| {}) {
+        Err(Error::Device { status, .. }) => assert_eq!(status, Status::ErrAddress),
+        other => panic!("unexpected result {:?}", other),
+    }
+}
+
+#[test]
+fn timeout() {
+    let mut dfu = Dfu::new(FakeToboot::new().stuck_busy(Duration::from_millis(5)))
+        .with_timeout(Duration::from_millis(50));
+
+    match dfu.download(&firmware(100), |_| {}) {
+        Err(Error::Timeout(state)) => assert_eq!(state, State::DfuDnbusy),
+        other => panic!("unexpected result {:?}", other),
+    }
+}
+
+#[test]
+fn disconnect() {
+    let mut dfu = Dfu::new(FakeToboot::new().disconnect_at_block(1));
+
+    match dfu.download(&firmware(4096), |_| {}) {
+        Err(Error::Transport(err)) => assert_eq!(err, fake::Error::Disconnected),
+        other => panic!("unexpected result {:?}", other),
+    }
+}
+
+#[test]
+fn get_status_roundtrip() {
+    let status = GetStatus {
+        status: Status::ErrVerify,
+        poll_timeout: Duration::from_millis(0x012345),
+        state: State::DfuDnbusy,
+    };
+
+    assert_eq!(GetStatus::from_bytes(&status.to_bytes()), Some(status));
+}
+
+#[test]
+fn functional_descriptor() {
+    // Interface association noise first, then DFU functional descriptor.
+    let extra = [
+        3, 0x24, 0, //
+        9, 0x21, 0x0b, 0xe8, 0x03, 0x00, 0x04, 0x10, 0x01,
+    ];
+
+    assert_eq!(
+        FunctionalDescriptor::find(&extra),
+        Some(FunctionalDescriptor {
+            manifestation_tolerant: false,
+            will_detach: true,
+            detach_timeout: Duration::from_millis(1000),
+            transfer_size: 1024,
+        })
+    );
+    assert_eq!(FunctionalDescriptor::find(&extra[..3]), None);
+}
+
+#[test]
+fn select_toboot() {
+    let devices = [
+        DeviceInfo {
+            vid: 0x046d,
+            pid: 0xc52b,
+            dfu_protocol: None,
+        },
+        DeviceInfo {
+            vid: TOMU_VID,
+            pid: 0x70b2,
+            dfu_protocol: Some(PROTOCOL_RUNTIME),
+        },
+        DeviceInfo {
+            vid: TOMU_VID,
+            pid: TOBOOT_PID,
+            dfu_protocol: Some(PROTOCOL_DFU),
+        },
+    ];
+
+    assert_eq!(
+        dfu::select_device(&devices, TOMU_VID, TOBOOT_PID, true),
+        Some(2)
+    );
+}
+
+#[test]
+fn select_runtime_skips_other_vendors() {
+    // A dock with its own DFU run-time interface, must not be detached.
+    let devices = [
+        DeviceInfo {
+            vid: 0x17ef,
+            pid: 0x30b4,
+            dfu_protocol: Some(PROTOCOL_RUNTIME),
+        },
+        DeviceInfo {
+            vid: TOMU_VID,
+            pid: 0x70b2,
+            dfu_protocol: Some(PROTOCOL_RUNTIME),
+        },
+    ];
+
+    assert_eq!(
+        dfu::select_device(&devices, TOMU_VID, TOBOOT_PID, true),
+        Some(1)
+    );
+    assert_eq!(
+        dfu::select_device(&devices[..1], TOMU_VID, TOBOOT_PID, true),
+        None
+    );
+}
+
+#[test]
+fn select_after_detach_skips_runtime() {
+    let devices = [DeviceInfo {
+        vid: TOMU_VID,
+        pid: 0x70b2,
+        dfu_protocol: Some(PROTOCOL_RUNTIME),
+    }];
+
+    assert_eq!(
+        dfu::select_device(&devices, TOMU_VID, TOBOOT_PID, false),
+        None
+    );
+}
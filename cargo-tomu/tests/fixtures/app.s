@@ -0,0 +1,46 @@
+    .syntax unified
+    .cpu cortex-m0plus
+    .thumb
+
+    .section .vector_table, "a"
+    .word   0x20002000
+    .word   reset + 1
+    .rept   35
+    .word   default_handler + 1
+    .endr
+
+    .section .toboot, "a"
+    .word   0x907070b2      @ magic
+    .short  0               @ reserved_gen
+    .byte   16              @ start
+    .byte   2               @ config: autorun_enable
+    .word   0               @ lock_entry
+    .word   0               @ erase_mask_lo
+    .word   0x00000100      @ erase_mask_hi: sector 40
+    .word   0               @ reserved_hash
+
+    .section .text, "ax"
+    .globl  reset
+    .thumb_func
+reset:
+    ldr     r0, =counter
+    ldr     r1, [r0]
+    adds    r1, r1, #1
+    str     r1, [r0]
+    b       reset
+
+    .thumb_func
+default_handler:
+    b       default_handler
+
+    .section .rodata, "a"
+message:
+    .asciz  "hello from tomu"
+
+    .section .data, "aw"
+counter:
+    .word   0xdeadbeef
+
+    .section .bss, "aw", %nobits
+buffer:
+    .space  64
@@ -0,0 +1,17 @@
+MEMORY
+{
+  FLASH : ORIGIN = 0x00004000, LENGTH = 0xC000
+  RAM : ORIGIN = 0x20000000, LENGTH = 8K
+}
+
+ENTRY(reset);
+
+SECTIONS
+{
+  .vector_table ORIGIN(FLASH) : { KEEP(*(.vector_table)) } > FLASH
+  .toboot : { KEEP(*(.toboot)) } > FLASH
+  .text : { *(.text .text.*) } > FLASH
+  .rodata : ALIGN(4) { *(.rodata .rodata.*) . = ALIGN(4); } > FLASH
+  .data : ALIGN(4) { *(.data .data.*) . = ALIGN(4); } > RAM AT>FLASH
+  .bss (NOLOAD) : ALIGN(4) { *(.bss .bss.*) } > RAM
+}
@@ -0,0 +1,43 @@
+//! LED pattern example: heartbeat and morse without blocking.
+//!
+//! This examples shows:
+//!  * how to drive `Pattern` from a periodic `Timer`.
+//!  * how to play two patterns at once, the main loop stays free.
+
+#![no_std]
+#![no_main]
+
+use cortex_m_rt::entry;
+use panic_halt as _;
+use tomu::{
+    led::Pattern,
+    prelude::*,
+    timer::{Hertz, Timer},
+};
+
+/// Pattern tick period.
+const TICK_MS: u32 = 10;
+
+#[entry]
+fn main() -> ! {
+    let dp = efm32hg::Peripherals::take().unwrap();
+    let timer0 = dp.TIMER0;
+
+    let mut tomu = Tomu::from_parts(dp.CMU, dp.WDOG, dp.GPIO, dp.SYST);
+    tomu.watchdog.disable();
+
+    let mut timer = Timer::timer0(timer0, &tomu.clocks);
+    timer.start(Hertz(1000 / TICK_MS));
+
+    let mut heartbeat = Pattern::heartbeat(TICK_MS);
+    let mut morse = Pattern::morse("hello tomu", TICK_MS);
+
+    loop {
+        if timer.wait().is_ok() {
+            heartbeat.tick(&mut tomu.leds.green);
+            morse.tick(&mut tomu.leds.red);
+        }
+
+        // Anything else goes here.
+    }
+}
@@ -0,0 +1,62 @@
+//! LEUART example: receive while sleeping in EM2.
+//!
+//! This examples shows:
+//!  * how to configure LEUART0 at 9600 baud from LFRCO.
+//!  * how to put the core into EM2 (deep sleep) and wake up on received data.
+//!
+//! Connect a 3.3V serial adapter to PB13 (TX) and PB14 (RX), every byte
+//! received will wake the core up, be echoed back, and blink the green led.
+
+#![no_std]
+#![no_main]
+
+use cortex_m_rt::entry;
+use panic_halt as _;
+use tomu::{
+    clocks::{LfConfig, LfOscillator},
+    efm32,
+    leuart::{Event, Serial},
+    prelude::*,
+};
+
+#[entry]
+fn main() -> ! {
+    let mut dp = efm32hg::Peripherals::take().unwrap();
+    let leuart0 = dp.LEUART0;
+
+    // Enter EM2 on `wfi`.
+    dp.SCB.set_sleepdeep();
+
+    let mut tomu = Tomu::builder()
+        .lf_clocks(LfConfig::new().lfb(LfOscillator::Lfrco))
+        .build(dp.CMU, dp.WDOG, dp.GPIO, dp.SYST);
+    tomu.watchdog.disable();
+
+    tomu.leds.red.off();
+    tomu.leds.green.off();
+
+    let mut serial = Serial::new(
+        leuart0,
+        (tomu.gpio.pb13, tomu.gpio.pb14),
+        9600,
+        tomu.lf_clocks.leuart0.take().unwrap(),
+    );
+
+    // Interrupt is only used as a wake up source, with PRIMASK set the core
+    // still wakes up from `wfi` but the handler is never run.
+    serial.listen(Event::RxDataValid);
+    cortex_m::interrupt::disable();
+    unsafe { efm32::NVIC::unmask(efm32::Interrupt::LEUART0) };
+
+    loop {
+        cortex_m::asm::wfi();
+
+        while let Ok(byte) = serial.read() {
+            tomu.leds.green.on();
+            let _ = nb::block!(serial.write(byte));
+            tomu.leds.green.off();
+        }
+
+        efm32::NVIC::unpend(efm32::Interrupt::LEUART0);
+    }
+}
@@ -24,14 +24,11 @@ use cortex_m_rt::entry;
 
 use critical_section::Mutex;
 
-use efm32_hal::delay;
+use cortex_m::delay::Delay;
 use tomu::{interrupt, prelude::*};
 
-// NOTE: the following value follows the mcu frequency divided by 1024
-// e.g.
-// 1367 -> 14 MHz
-// 2051 -> 21 MHz
-const TIMER1_TOP_VALUE: u16 = 1367u16;
+// Scan time, TIMER1 overflows at this rate.
+const SCAN_HZ: u32 = 10;
 
 const CAPSENSE_THRESHOLD_VALUE: u16 = 400;
 
@@ -39,7 +36,7 @@ static _ACMP0: Mutex<RefCell<Option<efm32::ACMP0>>> = Mutex::new(RefCell::new(No
 static _TIMER0: Mutex<RefCell<Option<efm32::TIMER0>>> = Mutex::new(RefCell::new(None));
 static _TIMER1: Mutex<RefCell<Option<efm32::TIMER1>>> = Mutex::new(RefCell::new(None));
 
-static DELAY: Mutex<RefCell<Option<delay::Delay>>> = Mutex::new(RefCell::new(None));
+static DELAY: Mutex<RefCell<Option<Delay>>> = Mutex::new(RefCell::new(None));
 
 static GREENLED: Mutex<RefCell<Option<led::GreenLED>>> = Mutex::new(RefCell::new(None));
 static REDLED: Mutex<RefCell<Option<led::RedLED>>> = Mutex::new(RefCell::new(None));
@@ -63,6 +60,11 @@ fn main() -> ! {
     let mut tomu = Tomu::from_parts(efm32.CMU, efm32.WDOG, efm32.GPIO, efm32.SYST);
     tomu.watchdog.disable();
 
+    // scan time 100ms, HFPERCLK divided by 1024
+    timer1
+        .top
+        .write(|w| unsafe { w.top().bits((tomu.clocks.hfperclk() / 1024 / SCAN_HZ) as u16) });
+
     // NOTE: toboot v2.0-rc7 and below has issues with GPIO pin reset
     // you may want to call the following
     // tomu.gpio.pc1.into_input();
@@ -237,10 +239,5 @@ fn timer_setup(efm32: &efm32hg::Peripherals) {
         .write(|w| unsafe { w.edsel().posedge().sourcesel().acmp0().sigsel().bits(0u8) });
 
     efm32.TIMER1.ctrl.write(|w| w.presc().div1024());
-
-    // scan time 100ms
-    efm32.TIMER1
-        .top
-        .write(|w| unsafe { w.top().bits(TIMER1_TOP_VALUE) });
     efm32.TIMER1.ien.write(|w| w.of().set_bit());
 }
@@ -1,7 +1,7 @@
 //! PAC example: RTC interrupt toggling green LED.
 //!
 //! This examples shows:
-//!  * how to configure clock to RTC.
+//!  * how to clock RTC from LFRCO with `LfConfig`.
 //!  * how to configure RTC parameters.
 //!  * how to configure and handle RTC interrupts.
 //!
@@ -15,51 +15,50 @@ use core::ops::DerefMut;
 use critical_section::Mutex;
 use cortex_m_rt::entry;
 use panic_halt as _;
-use tomu::{efm32, efm32::interrupt, prelude::*};
+use tomu::{
+    clocks::{LfConfig, LfOscillator},
+    efm32,
+    efm32::interrupt,
+    prelude::*,
+};
 
 static GREEN: Mutex<RefCell<Option<led::GreenLED>>> = Mutex::new(RefCell::new(None));
 
 #[entry]
 fn main() -> ! {
     let dp = efm32hg::Peripherals::take().unwrap();
+    let rtc = dp.RTC;
 
-    // Configure clock to RTC:
-    //  * LFRCO ticks at 32768 Hz
-    //  * No clock divider
-    dp.CMU.hfcoreclken0.write(|w| w.le().set_bit());
-    dp.CMU.oscencmd.write(|w| w.lfrcoen().set_bit());
-    dp.CMU.lfapresc0.reset();
-    dp.CMU.lfclksel.write(|w| w.lfa().lfrco());
+    // Clock RTC from LFRCO, ticking at 32768 Hz with no clock divider.
+    let mut tomu = Tomu::builder()
+        .lf_clocks(LfConfig::new().lfa(LfOscillator::Lfrco))
+        .build(dp.CMU, dp.WDOG, dp.GPIO, dp.SYST);
+    tomu.watchdog.disable();
 
-    // Enable clock to RTC, ticking at 32 KiHz.
-    dp.CMU.lfaclken0.write(|w| w.rtc().set_bit());
+    let rtc_clock = tomu.lf_clocks.rtc.take().unwrap();
 
     // Reset RTC
-    dp.RTC.freeze.reset();
-    dp.RTC.ctrl.reset();
-    dp.RTC.ien.reset();
-    dp.RTC.ifc
+    rtc.freeze.reset();
+    rtc.ctrl.reset();
+    rtc.ien.reset();
+    rtc.ifc
         .write(|w| w.comp0().set_bit().comp1().set_bit().of().set_bit());
-    dp.RTC.comp0.reset();
-    dp.RTC.comp1.reset();
+    rtc.comp0.reset();
+    rtc.comp1.reset();
 
-    // Interrupt when matching custom compare value:
-    // 65536 / 32768 Hz = 2 secs
-    dp.RTC.comp0.write(|w| unsafe { w.comp0().bits(65_536) });
-    dp.RTC.ien.modify(|_, w| w.comp0().set_bit());
+    // Interrupt when matching custom compare value: every 2 secs
+    rtc.comp0.write(|w| unsafe { w.comp0().bits(2 * rtc_clock.hz()) });
+    rtc.ien.modify(|_, w| w.comp0().set_bit());
 
     // Cap counter at `comp0` value.
-    dp.RTC.ctrl.modify(|_, w| w.comp0top().set_bit());
+    rtc.ctrl.modify(|_, w| w.comp0top().set_bit());
 
     // Enable RTC interrupts.
     efm32::NVIC::unpend(efm32::Interrupt::RTC);
     unsafe { efm32::NVIC::unmask(efm32::Interrupt::RTC) };
 
     // Start RTC.
-    dp.RTC.ctrl.modify(|_, w| w.en().set_bit());
-
-    let mut tomu = Tomu::from(dp);
-    tomu.watchdog.disable();
+    rtc.ctrl.modify(|_, w| w.en().set_bit());
 
     tomu.leds.red.off();
     tomu.leds.green.off();
@@ -0,0 +1,32 @@
+//! PWM example: breathing green led.
+//!
+//! This examples shows:
+//!  * how to turn the on-board led into a PWM dimmed `PwmGreenLED`.
+//!  * how to fade it with `set_brightness`, gamma corrected.
+
+#![no_std]
+#![no_main]
+
+use cortex_m_rt::entry;
+use panic_halt as _;
+use tomu::{led::PwmGreenLED, prelude::*};
+
+#[entry]
+fn main() -> ! {
+    let dp = efm32hg::Peripherals::take().unwrap();
+    let timer0 = dp.TIMER0;
+
+    let mut tomu = Tomu::from_parts(dp.CMU, dp.WDOG, dp.GPIO, dp.SYST);
+    tomu.watchdog.disable();
+
+    tomu.leds.red.off();
+
+    let mut green = PwmGreenLED::new(tomu.leds.green, timer0, &tomu.clocks);
+
+    loop {
+        for brightness in (0..=255u8).chain((0..=255u8).rev()) {
+            green.set_brightness(brightness);
+            tomu.delay.delay_ms(4u16);
+        }
+    }
+}
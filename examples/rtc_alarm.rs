@@ -0,0 +1,76 @@
+//! RTC example: blink the green led from an alarm, sleeping in between.
+//!
+//! This examples shows:
+//!  * how to create `Rtc` from an LF clock token.
+//!  * how to re-arm an alarm and handle it from the RTC interrupt.
+//!
+//! It requires the "unproven" feature for LED toggling.
+
+#![no_std]
+#![no_main]
+
+use core::cell::RefCell;
+use core::ops::DerefMut;
+use critical_section::Mutex;
+use cortex_m_rt::entry;
+use panic_halt as _;
+use tomu::{
+    clocks::{LfConfig, LfOscillator},
+    efm32,
+    efm32::interrupt,
+    prelude::*,
+    rtc::{Alarm, Rtc},
+};
+
+static _RTC: Mutex<RefCell<Option<Rtc>>> = Mutex::new(RefCell::new(None));
+static GREEN: Mutex<RefCell<Option<led::GreenLED>>> = Mutex::new(RefCell::new(None));
+
+#[entry]
+fn main() -> ! {
+    let dp = efm32hg::Peripherals::take().unwrap();
+    let rtc = dp.RTC;
+
+    let mut tomu = Tomu::builder()
+        .lf_clocks(LfConfig::new().lfa(LfOscillator::Lfrco))
+        .build(dp.CMU, dp.WDOG, dp.GPIO, dp.SYST);
+    tomu.watchdog.disable();
+
+    tomu.leds.red.off();
+    tomu.leds.green.off();
+
+    let mut rtc = Rtc::new(rtc, tomu.lf_clocks.rtc.take().unwrap());
+    let half_second = u64::from(rtc.hz() / 2);
+    let at = rtc.ticks() + half_second;
+    rtc.set_alarm(Alarm::Alarm0, at);
+
+    critical_section::with(|lock| {
+        _RTC.borrow(lock).replace(Some(rtc));
+        GREEN.borrow(lock).replace(Some(tomu.leds.green));
+    });
+
+    efm32::NVIC::unpend(efm32::Interrupt::RTC);
+    unsafe { efm32::NVIC::unmask(efm32::Interrupt::RTC) };
+
+    loop {
+        cortex_m::asm::wfi();
+    }
+}
+
+/// Interrupt handler for RTC events (overflow and alarms).
+#[interrupt]
+fn RTC() {
+    critical_section::with(|lock| {
+        if let Some(ref mut rtc) = _RTC.borrow(lock).borrow_mut().deref_mut() {
+            rtc.on_interrupt();
+
+            if rtc.take_alarm(Alarm::Alarm0) {
+                let at = rtc.ticks() + u64::from(rtc.hz() / 2);
+                rtc.set_alarm(Alarm::Alarm0, at);
+
+                if let Some(ref mut green) = GREEN.borrow(lock).borrow_mut().deref_mut() {
+                    green.toggle();
+                }
+            }
+        }
+    });
+}
@@ -0,0 +1,41 @@
+//! Timer example: blink the green led at 2 Hz from TIMER0.
+//!
+//! This examples shows:
+//!  * how to create `Timer` from the frozen clocks.
+//!  * how to use it as a periodic `CountDown`, at 21 MHz HFRCO.
+//!
+//! It requires the "unproven" feature for LED toggling.
+
+#![no_std]
+#![no_main]
+
+use cortex_m_rt::entry;
+use panic_halt as _;
+use tomu::{
+    clocks::HfrcoBand,
+    prelude::*,
+    timer::{Hertz, Timer},
+};
+
+#[entry]
+fn main() -> ! {
+    let dp = efm32hg::Peripherals::take().unwrap();
+    let timer0 = dp.TIMER0;
+
+    let mut tomu = Tomu::builder()
+        .hfrco(HfrcoBand::MHz21)
+        .build(dp.CMU, dp.WDOG, dp.GPIO, dp.SYST);
+    tomu.watchdog.disable();
+
+    tomu.leds.red.off();
+    tomu.leds.green.off();
+
+    // Toggling twice per period.
+    let mut timer = Timer::timer0(timer0, &tomu.clocks);
+    timer.start(Hertz(4));
+
+    loop {
+        let _ = nb::block!(timer.wait());
+        tomu.leds.green.toggle();
+    }
+}
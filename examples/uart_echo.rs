@@ -0,0 +1,42 @@
+//! UART echo example.
+//!
+//! Connect a 3.3V serial adapter to PC0 (TX) and PC1 (RX) at 115200 baud,
+//! any character received will be sent back, while toggling the green led.
+//!
+//! It requires the "unproven" feature for LED toggling.
+
+#![no_std]
+#![no_main]
+
+use core::fmt::Write as _;
+use cortex_m_rt::entry;
+use panic_halt as _;
+use tomu::{prelude::*, uart::Serial};
+
+#[entry]
+fn main() -> ! {
+    let dp = efm32hg::Peripherals::take().unwrap();
+    let usart0 = dp.USART0;
+
+    let mut tomu = Tomu::from_parts(dp.CMU, dp.WDOG, dp.GPIO, dp.SYST);
+    tomu.watchdog.disable();
+
+    tomu.leds.red.off();
+    tomu.leds.green.off();
+
+    let mut serial = Serial::usart0(
+        usart0,
+        (tomu.gpio.pc0, tomu.gpio.pc1),
+        115_200,
+        &tomu.clocks,
+    );
+
+    writeln!(serial, "hello from tomu!\r").unwrap();
+
+    loop {
+        if let Ok(byte) = nb::block!(serial.read()) {
+            let _ = nb::block!(serial.write(byte));
+            tomu.leds.green.toggle();
+        }
+    }
+}
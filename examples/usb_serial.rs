@@ -0,0 +1,90 @@
+//! USB serial echo example.
+//!
+//! This examples shows:
+//!  * how to clock the core from USHFRCO, which USB needs.
+//!  * how to follow USHFRCO clock recovery, the red led is on until it locks.
+//!  * how to create `UsbBus` and build a CDC-ACM device on top of it.
+//!
+//! Open the serial port (e.g. /dev/ttyACM0) and any character sent
+//! will be echoed back in uppercase.
+
+#![no_std]
+#![no_main]
+
+use core::ptr::addr_of_mut;
+use cortex_m_rt::entry;
+use panic_halt as _;
+use tomu::{
+    clocks::{ClockRecovery, RecoveryStatus},
+    prelude::*,
+    usb::{UsbBus, USB},
+};
+use usb_device::prelude::*;
+use usbd_serial::{SerialPort, USB_CLASS_CDC};
+
+static mut EP_MEMORY: [u32; 256] = [0; 256];
+
+#[entry]
+fn main() -> ! {
+    let dp = efm32hg::Peripherals::take().unwrap();
+
+    let usb = dp.USB;
+    // Run HFCLK from USHFRCO divided by 2 (24 MHz).
+    let mut tomu = Tomu::builder()
+        .ushfrco()
+        .build(dp.CMU, dp.WDOG, dp.GPIO, dp.SYST);
+    tomu.watchdog.disable();
+
+    tomu.leds.red.on();
+    tomu.leds.green.off();
+
+    let usb_bus = UsbBus::new(USB::new(usb, &tomu.clocks), unsafe { &mut *addr_of_mut!(EP_MEMORY) });
+
+    let mut serial = SerialPort::new(&usb_bus);
+
+    let mut usb_dev = UsbDeviceBuilder::new(&usb_bus, UsbVidPid(0x1209, 0x70b1))
+        .manufacturer("Tomu")
+        .product("Serial port")
+        .serial_number("TOMU")
+        .device_class(USB_CLASS_CDC)
+        .build();
+
+    let mut recovery = ClockRecovery::new();
+
+    loop {
+        if recovery.poll() == RecoveryStatus::Locked {
+            tomu.leds.red.off();
+        } else {
+            tomu.leds.red.on();
+        }
+
+        if !usb_dev.poll(&mut [&mut serial]) {
+            continue;
+        }
+
+        let mut buf = [0u8; 64];
+
+        match serial.read(&mut buf) {
+            Ok(count) if count > 0 => {
+                tomu.leds.green.on();
+
+                for c in buf[0..count].iter_mut() {
+                    c.make_ascii_uppercase();
+                }
+
+                let mut write_offset = 0;
+                while write_offset < count {
+                    match serial.write(&buf[write_offset..count]) {
+                        Ok(len) if len > 0 => {
+                            write_offset += len;
+                        }
+                        _ => {}
+                    }
+                }
+
+                tomu.leds.green.off();
+            }
+            _ => {}
+        }
+    }
+}
@@ -0,0 +1,506 @@
+//! Host side support for toboot images
+//!
+//! Parse, validate, and produce application images (raw `.bin`) for
+//! Tomu Bootloader (toboot v2). This is meant to be used from build or
+//! release tooling, so a bad header is caught before flashing instead of
+//! on the device.
+//!
+//! ``` no_run
+//! use tomu_image::Image;
+//!
+//! let image = Image::from_bin(std::fs::read("blink.bin").unwrap()).unwrap();
+//! image.validate().unwrap();
+//!
+//! println!("config: {:?}", image.config());
+//! ```
+//!
+//! Without the default `std` feature only the header codec and its checks
+//! are left, which is what `tomu` itself uses on the device.
+#![cfg_attr(not(feature = "std"), no_std)]
+
+use core::fmt;
+
+use xxhash_rust::xxh32::xxh32;
+
+pub const TOBOOT_V2_MAGIC: u32 = 0x907070b2;
+pub const TOBOOT_LOCK_ENTRY_MAGIC: u32 = 0x18349420;
+
+pub const TOBOOT_CONFIG_FLAG_ENABLE_IRQ: u8 = 1 << 0;
+pub const TOBOOT_CONFIG_FLAG_AUTORUN: u8 = 1 << 1;
+
+/// Seed used by toboot to hash the config header.
+pub const TOBOOT_HASH_SEED: u32 = 0x037a5ba6;
+
+/// Toboot looks for the config header right after the vector table,
+/// which is 37 words long on efm32hg (16 system exceptions, 21 interrupts).
+pub const CONFIG_OFFSET: usize = 0x94;
+
+/// Size of the config header in bytes.
+pub const CONFIG_SIZE: usize = 24;
+
+/// efm32hg309f64 flash size, 64KiB.
+pub const FLASH_SIZE: usize = 0x10000;
+
+/// Flash page (sector) size, 1KiB.
+pub const PAGE_SIZE: usize = 1024;
+
+/// Sectors below this one are owned by toboot.
+pub const TOBOOT_SECTORS: u8 = 16;
+
+/// Image validation error
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// Image is too short to contain the config header
+    TooShort(usize),
+    /// Config header magic is not `TOBOOT_V2_MAGIC`
+    InvalidMagic(u32),
+    /// Unknown bits are set in config flags
+    InvalidFlags(u8),
+    /// Program start page is inside toboot sectors
+    InvalidStart(u8),
+    /// `lock_entry` is neither 0 nor `TOBOOT_LOCK_ENTRY_MAGIC`
+    InvalidLockEntry(u32),
+    /// Erase mask sets bits for toboot sectors
+    TobootSectorsErased(u64),
+    /// Erase mask sets bits for sectors used by the image itself
+    ImageSectorsErased(u64),
+    /// Image doesn't fit in flash when placed at its start page
+    ImageTooLarge(usize),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::TooShort(len) => write!(
+                f,
+                "image is {} bytes, too short to contain config header at {:#x}",
+                len, CONFIG_OFFSET
+            ),
+            Error::InvalidMagic(magic) => write!(
+                f,
+                "invalid config header magic {:#010x}, expecting {:#010x}",
+                magic, TOBOOT_V2_MAGIC
+            ),
+            Error::InvalidFlags(flags) => write!(f, "unknown config flags {:#04x}", flags),
+            Error::InvalidStart(start) => write!(
+                f,
+                "program start page {} is inside toboot sectors (0-{})",
+                start,
+                TOBOOT_SECTORS - 1
+            ),
+            Error::InvalidLockEntry(lock) => write!(f, "invalid lock entry value {:#010x}", lock),
+            Error::TobootSectorsErased(mask) => write!(
+                f,
+                "erase mask {:#018x} sets bits for toboot sectors (0-{})",
+                mask,
+                TOBOOT_SECTORS - 1
+            ),
+            Error::ImageSectorsErased(mask) => {
+                write!(f, "erase mask {:#018x} overlaps with the image itself", mask)
+            }
+            Error::ImageTooLarge(len) => write!(f, "image is {} bytes, doesn't fit in flash", len),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for Error {}
+
+/// Toboot config header, same layout as `tomu::toboot::TobootConfig`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TobootConfig {
+    pub magic: u32,
+    pub reserved_gen: u16,
+    pub start: u8,
+    pub config: u8,
+    pub lock_entry: u32,
+    pub erase_mask_lo: u32,
+    pub erase_mask_hi: u32,
+    pub reserved_hash: u32,
+}
+
+impl TobootConfig {
+    /// Decode config header from its in-flash (little endian) representation.
+    pub fn from_bytes(bytes: &[u8; CONFIG_SIZE]) -> Self {
+        let u32_at = |offset: usize| {
+            u32::from_le_bytes([
+                bytes[offset],
+                bytes[offset + 1],
+                bytes[offset + 2],
+                bytes[offset + 3],
+            ])
+        };
+
+        TobootConfig {
+            magic: u32_at(0),
+            reserved_gen: u16::from_le_bytes([bytes[4], bytes[5]]),
+            start: bytes[6],
+            config: bytes[7],
+            lock_entry: u32_at(8),
+            erase_mask_lo: u32_at(12),
+            erase_mask_hi: u32_at(16),
+            reserved_hash: u32_at(20),
+        }
+    }
+
+    /// Encode config header into its in-flash (little endian) representation.
+    pub fn to_bytes(&self) -> [u8; CONFIG_SIZE] {
+        let mut bytes = [0u8; CONFIG_SIZE];
+
+        bytes[0..4].copy_from_slice(&self.magic.to_le_bytes());
+        bytes[4..6].copy_from_slice(&self.reserved_gen.to_le_bytes());
+        bytes[6] = self.start;
+        bytes[7] = self.config;
+        bytes[8..12].copy_from_slice(&self.lock_entry.to_le_bytes());
+        bytes[12..16].copy_from_slice(&self.erase_mask_lo.to_le_bytes());
+        bytes[16..20].copy_from_slice(&self.erase_mask_hi.to_le_bytes());
+        bytes[20..24].copy_from_slice(&self.reserved_hash.to_le_bytes());
+
+        bytes
+    }
+
+    /// Compute header hash the same way toboot does,
+    /// XXH32 of the whole header minus the `reserved_hash` field.
+    pub fn compute_hash(&self) -> u32 {
+        xxh32(&self.to_bytes()[..CONFIG_SIZE - 4], TOBOOT_HASH_SEED)
+    }
+
+    /// Whether `reserved_hash` matches the header content.
+    pub fn hash_matches(&self) -> bool {
+        self.reserved_hash == self.compute_hash()
+    }
+
+    /// Combined erase mask for sectors 0-63.
+    pub fn erase_mask(&self) -> u64 {
+        u64::from(self.erase_mask_hi) << 32 | u64::from(self.erase_mask_lo)
+    }
+
+    pub fn irq_enabled(&self) -> bool {
+        self.config & TOBOOT_CONFIG_FLAG_ENABLE_IRQ != 0
+    }
+
+    pub fn autorun_enabled(&self) -> bool {
+        self.config & TOBOOT_CONFIG_FLAG_AUTORUN != 0
+    }
+
+    pub fn entry_locked(&self) -> bool {
+        self.lock_entry == TOBOOT_LOCK_ENTRY_MAGIC
+    }
+
+    /// Check the header on its own, see `Image::validate` to also check
+    /// against the image it belongs to.
+    pub fn validate(&self) -> Result<(), Error> {
+        if self.magic != TOBOOT_V2_MAGIC {
+            return Err(Error::InvalidMagic(self.magic));
+        }
+
+        if self.config & !(TOBOOT_CONFIG_FLAG_ENABLE_IRQ | TOBOOT_CONFIG_FLAG_AUTORUN) != 0 {
+            return Err(Error::InvalidFlags(self.config));
+        }
+
+        if self.start < TOBOOT_SECTORS {
+            return Err(Error::InvalidStart(self.start));
+        }
+
+        if self.lock_entry != 0 && self.lock_entry != TOBOOT_LOCK_ENTRY_MAGIC {
+            return Err(Error::InvalidLockEntry(self.lock_entry));
+        }
+
+        let toboot_mask = (1u64 << TOBOOT_SECTORS) - 1;
+        if self.erase_mask() & toboot_mask != 0 {
+            return Err(Error::TobootSectorsErased(self.erase_mask()));
+        }
+
+        Ok(())
+    }
+}
+
+/// Application image as a raw binary, starting from its vector table
+#[cfg(feature = "std")]
+#[derive(Debug, Clone)]
+pub struct Image {
+    data: Vec<u8>,
+}
+
+#[cfg(feature = "std")]
+impl Image {
+    /// Wrap raw binary (e.g. `objcopy -O binary` output),
+    /// fails if it's too short to contain the config header.
+    pub fn from_bin(data: Vec<u8>) -> Result<Self, Error> {
+        if data.len() < CONFIG_OFFSET + CONFIG_SIZE {
+            return Err(Error::TooShort(data.len()));
+        }
+
+        Ok(Image { data })
+    }
+
+    /// Decode config header.
+    pub fn config(&self) -> TobootConfig {
+        let mut bytes = [0u8; CONFIG_SIZE];
+        bytes.copy_from_slice(&self.data[CONFIG_OFFSET..CONFIG_OFFSET + CONFIG_SIZE]);
+
+        TobootConfig::from_bytes(&bytes)
+    }
+
+    /// Replace config header.
+    pub fn set_config(&mut self, config: &TobootConfig) {
+        self.data[CONFIG_OFFSET..CONFIG_OFFSET + CONFIG_SIZE].copy_from_slice(&config.to_bytes());
+    }
+
+    /// Set generation and header hash, the way toboot rewrites the header
+    /// when the image is flashed.
+    pub fn finalize(&mut self, generation: u16) {
+        let mut config = self.config();
+        config.reserved_gen = generation;
+        config.reserved_hash = config.compute_hash();
+
+        self.set_config(&config);
+    }
+
+    /// Sectors (0-63) occupied by the image when placed at its start page.
+    pub fn sector_mask(&self) -> u64 {
+        let start = usize::from(self.config().start);
+        let end = (start * PAGE_SIZE + self.data.len()).div_ceil(PAGE_SIZE);
+
+        (start..end.min(64)).fold(0, |mask, sector| mask | 1 << sector)
+    }
+
+    /// Validate config header, and check that the image fits in flash
+    /// and won't be erased by its own erase masks.
+    pub fn validate(&self) -> Result<(), Error> {
+        let config = self.config();
+        config.validate()?;
+
+        if usize::from(config.start) * PAGE_SIZE + self.data.len() > FLASH_SIZE {
+            return Err(Error::ImageTooLarge(self.data.len()));
+        }
+
+        if config.erase_mask() & self.sector_mask() != 0 {
+            return Err(Error::ImageSectorsErased(config.erase_mask()));
+        }
+
+        Ok(())
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.data
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> TobootConfig {
+        TobootConfig {
+            magic: TOBOOT_V2_MAGIC,
+            reserved_gen: 0,
+            start: TOBOOT_SECTORS,
+            config: 0,
+            lock_entry: 0,
+            erase_mask_lo: 0,
+            erase_mask_hi: 0,
+            reserved_hash: 0,
+        }
+    }
+
+    fn image(config: &TobootConfig, len: usize) -> Image {
+        let mut image = Image::from_bin(vec![0xff; len]).unwrap();
+        image.set_config(config);
+        image
+    }
+
+    #[test]
+    fn header_round_trip() {
+        let config = TobootConfig {
+            reserved_gen: 0x1234,
+            config: TOBOOT_CONFIG_FLAG_AUTORUN,
+            lock_entry: TOBOOT_LOCK_ENTRY_MAGIC,
+            erase_mask_lo: 0x8000_0000,
+            erase_mask_hi: 0x0000_0001,
+            reserved_hash: 0xdead_beef,
+            ..config()
+        };
+
+        let bytes = config.to_bytes();
+        assert_eq!(bytes[..4], [0xb2, 0x70, 0x70, 0x90]);
+        assert_eq!(bytes[6], TOBOOT_SECTORS);
+        assert_eq!(TobootConfig::from_bytes(&bytes), config);
+        assert_eq!(config.erase_mask(), 0x0000_0001_8000_0000);
+    }
+
+    #[test]
+    fn valid_image() {
+        let config = TobootConfig {
+            config: TOBOOT_CONFIG_FLAG_ENABLE_IRQ | TOBOOT_CONFIG_FLAG_AUTORUN,
+            lock_entry: TOBOOT_LOCK_ENTRY_MAGIC,
+            erase_mask_hi: 0x8000_0000,
+            ..config()
+        };
+
+        assert_eq!(image(&config, 2 * PAGE_SIZE).validate(), Ok(()));
+    }
+
+    #[test]
+    fn too_short() {
+        let len = CONFIG_OFFSET + CONFIG_SIZE - 1;
+        assert_eq!(
+            Image::from_bin(vec![0; len]).unwrap_err(),
+            Error::TooShort(len)
+        );
+    }
+
+    #[test]
+    fn invalid_magic() {
+        let config = TobootConfig {
+            magic: 0x907070b1,
+            ..config()
+        };
+
+        assert_eq!(config.validate(), Err(Error::InvalidMagic(0x907070b1)));
+        assert_eq!(
+            image(&config, PAGE_SIZE).validate(),
+            Err(Error::InvalidMagic(0x907070b1))
+        );
+    }
+
+    #[test]
+    fn invalid_flags() {
+        let config = TobootConfig {
+            config: TOBOOT_CONFIG_FLAG_AUTORUN | 1 << 2,
+            ..config()
+        };
+
+        assert_eq!(config.validate(), Err(Error::InvalidFlags(0x06)));
+    }
+
+    #[test]
+    fn invalid_start() {
+        let config = TobootConfig {
+            start: TOBOOT_SECTORS - 1,
+            ..config()
+        };
+
+        assert_eq!(
+            config.validate(),
+            Err(Error::InvalidStart(TOBOOT_SECTORS - 1))
+        );
+    }
+
+    #[test]
+    fn invalid_lock_entry() {
+        let config = TobootConfig {
+            lock_entry: 1,
+            ..config()
+        };
+
+        assert_eq!(config.validate(), Err(Error::InvalidLockEntry(1)));
+    }
+
+    #[test]
+    fn toboot_sectors_erased() {
+        let config = TobootConfig {
+            erase_mask_lo: 1 << (TOBOOT_SECTORS - 1),
+            ..config()
+        };
+
+        assert_eq!(config.validate(), Err(Error::TobootSectorsErased(0x8000)));
+    }
+
+    #[test]
+    fn image_sectors_erased() {
+        // Image takes sectors 16 and 17.
+        let config = TobootConfig {
+            erase_mask_lo: 1 << 17,
+            ..config()
+        };
+
+        assert_eq!(config.validate(), Ok(()));
+        assert_eq!(
+            image(&config, PAGE_SIZE + 1).validate(),
+            Err(Error::ImageSectorsErased(1 << 17))
+        );
+        assert_eq!(image(&config, PAGE_SIZE).validate(), Ok(()));
+    }
+
+    #[test]
+    fn image_too_large() {
+        let len = FLASH_SIZE - usize::from(TOBOOT_SECTORS) * PAGE_SIZE;
+
+        assert_eq!(image(&config(), len).validate(), Ok(()));
+        assert_eq!(
+            image(&config(), len + 1).validate(),
+            Err(Error::ImageTooLarge(len + 1))
+        );
+    }
+
+    #[test]
+    fn compute_hash() {
+        let config = TobootConfig {
+            reserved_gen: 1,
+            config: TOBOOT_CONFIG_FLAG_AUTORUN,
+            ..config()
+        };
+
+        // XXH32 of the first 20 header bytes, seeded with TOBOOT_HASH_SEED.
+        assert_eq!(config.compute_hash(), 0x3ab0fc07);
+        assert!(!config.hash_matches());
+    }
+
+    #[test]
+    fn finalize() {
+        let config = TobootConfig {
+            config: TOBOOT_CONFIG_FLAG_AUTORUN,
+            reserved_hash: 0xdead_beef,
+            ..config()
+        };
+
+        let mut image = image(&config, PAGE_SIZE);
+        image.finalize(1);
+
+        let finalized = image.config();
+        assert_eq!(finalized.reserved_gen, 1);
+        assert_eq!(finalized.reserved_hash, 0x3ab0fc07);
+        assert!(finalized.hash_matches());
+
+        // Nothing but the header is touched.
+        assert!(image.as_bytes()[..CONFIG_OFFSET].iter().all(|&b| b == 0xff));
+        assert!(image.as_bytes()[CONFIG_OFFSET + CONFIG_SIZE..]
+            .iter()
+            .all(|&b| b == 0xff));
+    }
+
+    #[test]
+    fn sector_mask_boundaries() {
+        // Sectors 16-31, right up to the end of the low mask.
+        let len = 16 * PAGE_SIZE;
+        assert_eq!(image(&config(), len).sector_mask(), 0x0000_0000_ffff_0000);
+
+        // One more byte spills into sector 32.
+        assert_eq!(
+            image(&config(), len + 1).sector_mask(),
+            0x0000_0001_ffff_0000
+        );
+
+        // Image starting at sector 32.
+        let config = TobootConfig {
+            start: 32,
+            ..config()
+        };
+        assert_eq!(image(&config, PAGE_SIZE).sector_mask(), 1 << 32);
+
+        // Last sector of flash.
+        let config = TobootConfig {
+            start: 63,
+            ..config
+        };
+        assert_eq!(image(&config, PAGE_SIZE).sector_mask(), 1 << 63);
+
+        // Past the end of flash is clamped, instead of overflowing.
+        assert_eq!(image(&config, 2 * PAGE_SIZE).sector_mask(), 1 << 63);
+    }
+}
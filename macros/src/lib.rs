@@ -1,20 +1,27 @@
 extern crate proc_macro;
 
 use proc_macro2::Span;
-use quote::quote;
+use quote::{quote, quote_spanned};
 use syn::{
     parse::{self, Parse, ParseStream, Result},
     parse_macro_input,
     spanned::Spanned,
-    Expr, ExprArray, Ident, LitBool, LitInt, Token,
+    Expr, ExprArray, Ident, Lit, LitBool, LitInt, LitStr, RangeLimits, Token,
 };
 
-const TOBOOT_LOCK_ENTRY_MAGIC: u32 = 0x18349420;
+use tomu_image::TOBOOT_LOCK_ENTRY_MAGIC;
+
+/// Flash geometry from `tomu-image`, widened for range arithmetic.
+const FLASH_SIZE: u64 = tomu_image::FLASH_SIZE as u64;
+const PAGE_SIZE: u64 = tomu_image::PAGE_SIZE as u64;
+const TOBOOT_SECTORS: u64 = tomu_image::TOBOOT_SECTORS as u64;
 
 #[derive(Default)]
 struct ParsedTobootConfig {
     config: Option<ExprArray>,
+    erase: Option<ExprArray>,
     lock_entry: Option<LitBool>,
+    lock_entry_ack: Option<LitBool>,
     erase_mask_lo: Option<LitInt>,
     erase_mask_hi: Option<LitInt>,
 }
@@ -31,7 +38,7 @@ impl Parse for ParsedTobootConfig {
             }
 
             if !input.peek(Ident) {
-                return Err(input.error("expecting identifier, either `config`, `lock_entry`, `erase_mask_lo`, and/or `erase_mask_hi`"));
+                return Err(input.error("expecting identifier, either `config`, `lock_entry`, `i_understand_this_locks_the_bootloader`, `erase`, `erase_mask_lo`, and/or `erase_mask_hi`"));
             }
 
             let id: Ident = input.parse()?;
@@ -50,9 +57,23 @@ impl Parse for ParsedTobootConfig {
                         },
                     }
                 },
+                "erase" => {
+                    let expr: Expr = input.parse()?;
+                    match expr {
+                        Expr::Array(exp_array) => {
+                            result_config.erase = Some(exp_array);
+                        },
+                        _ => {
+                            return Err(input.error("expecting array of flash ranges or sectors, e.g. [0x8000..0xa000, sector(40)]"));
+                        },
+                    }
+                },
                 "lock_entry" => {
                     result_config.lock_entry = Some(input.parse()?);
                 },
+                "i_understand_this_locks_the_bootloader" => {
+                    result_config.lock_entry_ack = Some(input.parse()?);
+                },
                 "erase_mask_lo" => {
                     result_config.erase_mask_lo = Some(input.parse()?);
                 },
@@ -61,7 +82,7 @@ impl Parse for ParsedTobootConfig {
                 },
                 _ => return Err(parse::Error::new(
                     id.span(),
-                    &format!("unexpected identifier `{}`, expecting either `config`, `lock_entry`, `erase_mask_lo`, and/or `erase_mask_hi`", id.to_string())
+                    &format!("unexpected identifier `{}`, expecting either `config`, `lock_entry`, `i_understand_this_locks_the_bootloader`, `erase`, `erase_mask_lo`, and/or `erase_mask_hi`", id.to_string())
                 )),
             }
 
@@ -80,24 +101,182 @@ impl ParsedTobootConfig {
         }
     }
 
-    fn lock_entry_val(&self) -> u32 {
+    fn lock_entry_val(&self) -> Result<u32> {
         match &self.lock_entry {
-            Some(lock) if lock.value => TOBOOT_LOCK_ENTRY_MAGIC,
-            _ => 0,
+            Some(lock) if lock.value => {
+                match &self.lock_entry_ack {
+                    Some(ack) if ack.value => Ok(TOBOOT_LOCK_ENTRY_MAGIC),
+                    _ => Err(self.new_error(lock.span(), "setting `lock_entry` to `true` will _LOCK_ you out from \
+                        entering bootloader via outer-pin shorting. Unless you know what you're doing, you may want \
+                        to set `lock_entry` to `false` or remove the setting altogether. \
+                        To lock the bootloader anyway, also set `i_understand_this_locks_the_bootloader: true`")),
+                }
+            },
+            _ => match &self.lock_entry_ack {
+                Some(ack) if ack.value => Err(self.new_error(ack.span(), "`i_understand_this_locks_the_bootloader` \
+                    acknowledges `lock_entry: true`, which is not set")),
+                _ => Ok(0),
+            },
         }
     }
 
-    fn erase_mask_lo_val(&self) -> u32 {
-        match &self.erase_mask_lo {
-            Some(mask) => mask.value() as u32,
-            None => 0,
+    /// Use of a deprecated item, so rustc warns about the locked bootloader
+    /// on every build. Only called once `lock_entry_val` has passed.
+    fn lock_entry_warning(&self) -> proc_macro2::TokenStream {
+        match &self.lock_entry {
+            Some(lock) if lock.value => quote_spanned! {lock.span()=>
+                #[deprecated(note = "toboot `lock_entry` is set, the bootloader can no longer be entered by shorting the outer pins")]
+                #[allow(non_upper_case_globals)]
+                const toboot_lock_entry: () = ();
+                const _: () = toboot_lock_entry;
+            },
+            _ => quote! {},
         }
     }
 
-    fn erase_mask_hi_val(&self) -> u32 {
+    fn erase_mask_lo_val(&self) -> Result<u32> {
+        let mask = match &self.erase_mask_lo {
+            Some(mask) => mask,
+            None => return Ok(0),
+        };
+
+        let val = self.mask_to_u32(mask)?;
+        let toboot_mask = (1u32 << TOBOOT_SECTORS) - 1;
+
+        if val & toboot_mask != 0 {
+            return Err(self.new_error(mask.span(), &format!(
+                "erase_mask_lo {:#010x} sets bits for sectors 0-{}, these are owned by toboot and cannot be erased",
+                val,
+                TOBOOT_SECTORS - 1
+            )));
+        }
+
+        Ok(val)
+    }
+
+    fn erase_mask_hi_val(&self) -> Result<u32> {
         match &self.erase_mask_hi {
-            Some(mask) => mask.value() as u32,
-            None => 0,
+            Some(mask) => self.mask_to_u32(mask),
+            None => Ok(0),
+        }
+    }
+
+    fn mask_to_u32(&self, mask: &LitInt) -> Result<u32> {
+        let val = mask.value();
+
+        if val > u64::from(u32::MAX) {
+            return Err(self.new_error(mask.span(), &format!(
+                "erase mask {:#x} overflows 32 bit, each mask covers 32 sectors",
+                val
+            )));
+        }
+
+        Ok(val as u32)
+    }
+
+    /// Erase mask for all sectors (0-63) declared in `erase`
+    fn erase_val(&self) -> Result<u64> {
+        let erase = match &self.erase {
+            Some(erase) => erase,
+            None => return Ok(0),
+        };
+
+        let mut result: u64 = 0;
+
+        for item in erase.elems.iter() {
+            let (start, end) = self.expr_to_sectors(item)?;
+
+            for sector in start..end {
+                result |= 1 << sector;
+            }
+        }
+
+        Ok(result)
+    }
+
+    /// Convert either a flash address range or `sector(n)` into
+    /// half-open sector range
+    fn expr_to_sectors(&self, expr: &Expr) -> Result<(u64, u64)> {
+        let (start, end) = match expr {
+            Expr::Range(range) => {
+                let (from, to) = match (&range.from, &range.to) {
+                    (Some(from), Some(to)) => (from, to),
+                    _ => return Err(self.new_error(expr.span(), "flash range must have both start and end address, e.g. 0x8000..0xa000")),
+                };
+
+                let start = self.expr_to_int(from)?;
+                let end = match range.limits {
+                    RangeLimits::HalfOpen(_) => self.expr_to_int(to)?,
+                    RangeLimits::Closed(_) => self.expr_to_int(to)?.checked_add(1).ok_or_else(|| {
+                        syn::Error::new_spanned(to, "flash range end address overflows, expecting address within 64KiB flash")
+                    })?,
+                };
+
+                if start % PAGE_SIZE != 0 || end % PAGE_SIZE != 0 {
+                    return Err(self.new_error(expr.span(), &format!(
+                        "flash range {:#x}..{:#x} is not page-aligned, start and end address must be multiple of {:#x} (1KiB)",
+                        start, end, PAGE_SIZE
+                    )));
+                }
+
+                if start >= end {
+                    return Err(self.new_error(expr.span(), "flash range is empty"));
+                }
+
+                if end > FLASH_SIZE {
+                    return Err(self.new_error(expr.span(), &format!(
+                        "flash range {:#x}..{:#x} exceeds flash size ({:#x})",
+                        start, end, FLASH_SIZE
+                    )));
+                }
+
+                (start / PAGE_SIZE, end / PAGE_SIZE)
+            }
+            Expr::Call(call) if self.is_sector_call(call) => {
+                if call.args.len() != 1 {
+                    return Err(self.new_error(expr.span(), "expecting one sector number, e.g. sector(40)"));
+                }
+
+                let sector = self.expr_to_int(&call.args[0])?;
+
+                if sector >= FLASH_SIZE / PAGE_SIZE {
+                    return Err(self.new_error(expr.span(), &format!(
+                        "sector {} is out of flash, valid sectors are 0-{}",
+                        sector,
+                        FLASH_SIZE / PAGE_SIZE - 1
+                    )));
+                }
+
+                (sector, sector + 1)
+            }
+            _ => return Err(self.new_error(expr.span(), "unexpected token, expecting flash range (e.g. 0x8000..0xa000) or sector (e.g. sector(40))")),
+        };
+
+        if start < TOBOOT_SECTORS {
+            return Err(self.new_error(expr.span(), &format!(
+                "sectors 0-{} (0x0..{:#x}) are owned by toboot and cannot be erased",
+                TOBOOT_SECTORS - 1,
+                TOBOOT_SECTORS * PAGE_SIZE
+            )));
+        }
+
+        Ok((start, end))
+    }
+
+    fn is_sector_call(&self, call: &syn::ExprCall) -> bool {
+        match &*call.func {
+            Expr::Path(expr_path) => expr_path.path.is_ident("sector"),
+            _ => false,
+        }
+    }
+
+    fn expr_to_int(&self, expr: &Expr) -> Result<u64> {
+        match expr {
+            Expr::Lit(expr_lit) => match &expr_lit.lit {
+                Lit::Int(int) => Ok(int.value()),
+                _ => Err(self.new_error(expr.span(), "expecting integer literal")),
+            },
+            _ => Err(self.new_error(expr.span(), "expecting integer literal")),
         }
     }
 
@@ -141,7 +320,7 @@ impl ParsedTobootConfig {
 /// Function-like macro for toboot configuration
 /// **IMPORTANT** this macro may only be invoked once.
 ///
-/// This macro define `config`, `lock_entry`, `erase_mask_lo`, or `erase_mask_hi`
+/// This macro define `config`, `lock_entry`, `erase`, `erase_mask_lo`, or `erase_mask_hi`
 /// values to be used in tomu bootloader (toboot)
 ///
 /// Valid values:
@@ -150,11 +329,28 @@ impl ParsedTobootConfig {
 ///   either: [irq_enable] or [autorun_enable] or [irq_enable, autorun_enable]
 ///
 /// - `lock_entry` [bool]
-///   either: true or false
+///   either: true or false. Setting this to true will disable manual bootloader entry,
+///   and is a compile error unless `i_understand_this_locks_the_bootloader: true`
+///   is also set.
+///
+/// - `i_understand_this_locks_the_bootloader` [bool]
+///   acknowledgement required for `lock_entry: true`, and only accepted along with it.
+///   Locked config still compiles with a warning.
+///
+/// - `erase` [array]
+///   flash address ranges and/or sectors to be erased when updating program,
+///   e.g. [0x8000..0xa000, sector(40)]. Ranges have to be page-aligned (1KiB),
+///   and must not go beyond 64KiB flash or touch toboot sectors (0-15).
+///   This is converted into `erase_mask_lo` and `erase_mask_hi`.
 ///
 /// - erase_mask_lo (integer)
+///   must fit in 32 bit, and must not set bits for toboot sectors (0-15)
 ///
 /// - erase_mask_hi (integer)
+///   must fit in 32 bit
+///
+/// Erase masks are also checked at link time to not overlap with the
+/// application image itself.
 ///
 ///
 /// for example:
@@ -165,6 +361,7 @@ impl ParsedTobootConfig {
 /// toboot_config! {
 ///     config: [irq_enable, autorun_enable],
 ///     lock_entry: true,
+///     i_understand_this_locks_the_bootloader: true,
 /// }
 /// ```
 ///
@@ -175,13 +372,20 @@ impl ParsedTobootConfig {
 /// static CONFIG: tomu::toboot::TobootConfig = tomu::toboot::TobootConfig{
 ///     magic: tomu::toboot::TOBOOT_V2_MAGIC,
 ///     reserved_gen: 0,
-///     start: 16,
+///     start: tomu::layout::APP_START_PAGE as u8,
 ///     config: 3,
 ///     lock_entry: tomu::toboot::TOBOOT_LOCK_ENTRY_MAGIC,
 ///     erase_mask_lo: 0,
 ///     erase_mask_hi: 0,
 ///     reserved_hash: 0,
 /// };
+///
+/// core::arch::global_asm!(
+///     ".globl __toboot_erase_mask_lo__
+///      .set __toboot_erase_mask_lo__, 0x00000000
+///      .globl __toboot_erase_mask_hi__
+///      .set __toboot_erase_mask_hi__, 0x00000000"
+/// );
 /// ```
 #[proc_macro]
 pub fn toboot_config(input: crate::proc_macro::TokenStream) -> crate::proc_macro::TokenStream {
@@ -192,24 +396,44 @@ pub fn toboot_config(input: crate::proc_macro::TokenStream) -> crate::proc_macro
             return err.to_compile_error().into();
         }
     };
-    let lock_val = parsed_config.lock_entry_val();
-    let erase_mask_lo_val = parsed_config.erase_mask_lo_val();
-    let erase_mask_hi_val = parsed_config.erase_mask_hi_val();
-
-    // FIXME: revert this back to using proc_macro's `warning` when it's stabilized
-    if lock_val == TOBOOT_LOCK_ENTRY_MAGIC {
-        eprintln!(
-            r#"
-***************** CAUTION! *******************
-* Setting toboot lock entry to `true` will   *
-* _LOCK_ you out from entering bootloader.   *
-* Unless you know what you're doing, you may *
-* want to set `lock_entry` to `false` or     *
-* remove the setting altogether.             *
-**********************************************
-"#
-        );
-    }
+    let erase_val = match parsed_config.erase_val() {
+        Ok(val) => val,
+        Err(err) => {
+            return err.to_compile_error().into();
+        }
+    };
+    let lock_val = match parsed_config.lock_entry_val() {
+        Ok(val) => val,
+        Err(err) => {
+            return err.to_compile_error().into();
+        }
+    };
+    let lock_entry_warning = parsed_config.lock_entry_warning();
+    let erase_mask_lo_val = match parsed_config.erase_mask_lo_val() {
+        Ok(val) => val | erase_val as u32,
+        Err(err) => {
+            return err.to_compile_error().into();
+        }
+    };
+    let erase_mask_hi_val = match parsed_config.erase_mask_hi_val() {
+        Ok(val) => val | (erase_val >> 32) as u32,
+        Err(err) => {
+            return err.to_compile_error().into();
+        }
+    };
+
+    // Sectors used by the application image are only known at link time,
+    // so export the masks for memory.x to check against.
+    let erase_mask_symbols = LitStr::new(
+        &format!(
+            ".globl __toboot_erase_mask_lo__\n\
+             .set __toboot_erase_mask_lo__, {:#010x}\n\
+             .globl __toboot_erase_mask_hi__\n\
+             .set __toboot_erase_mask_hi__, {:#010x}",
+            erase_mask_lo_val, erase_mask_hi_val
+        ),
+        Span::call_site(),
+    );
 
     let result = quote! {
         #[used]
@@ -217,13 +441,17 @@ pub fn toboot_config(input: crate::proc_macro::TokenStream) -> crate::proc_macro
         static CONFIG: tomu::toboot::TobootConfig = tomu::toboot::TobootConfig{
             magic: tomu::toboot::TOBOOT_V2_MAGIC,
             reserved_gen: 0,
-            start: 16,
+            start: tomu::layout::APP_START_PAGE as u8,
             config: #config_val,
             lock_entry: #lock_val,
             erase_mask_lo: #erase_mask_lo_val,
             erase_mask_hi: #erase_mask_hi_val,
             reserved_hash: 0,
         };
+
+        core::arch::global_asm!(#erase_mask_symbols);
+
+        #lock_entry_warning
     };
 
     result.into()
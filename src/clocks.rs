@@ -0,0 +1,621 @@
+//! High frequency clock tree configuration
+//!
+//! After reset HFCLK runs from HFRCO at 14 MHz, undivided. `Config` picks
+//! another HFRCO band, or switches HFCLK to USHFRCO (48 MHz, divided by 2)
+//! which USB needs, and sets the HFCORECLK / HFPERCLK prescalers.
+//! Freezing it applies the configuration and returns `Clocks`, the
+//! frequencies drivers use to compute their dividers.
+//!
+//! Tomu has no crystal, so when running from USHFRCO its 48 MHz band is
+//! kept in tune by clock recovery against USB start-of-frame packets,
+//! `ClockRecovery` reports whether it has locked.
+//!
+//! Low energy peripherals run from the low frequency clock domain instead,
+//! LFA for RTC and LFB for LEUART0, while WDOG selects its oscillator
+//! directly. `LfConfig` enables the oscillators and routes them, freezing it
+//! hands out `LfClocks` tokens that the drivers take, so they can't be set
+//! up without a running clock.
+//!
+//! Usually this is done through `Tomu::builder()`:
+//!
+//! ``` no_run
+//! # use tomu::clocks::{HfrcoBand, Prescaler};
+//! # let p = tomu::efm32hg::Peripherals::take().unwrap();
+//! let tomu = tomu::Tomu::builder()
+//!     .hfrco(HfrcoBand::MHz21)
+//!     .hfper_prescaler(Prescaler::Div2)
+//!     .build(p.CMU, p.WDOG, p.GPIO, p.SYST);
+//!
+//! assert_eq!(tomu.clocks.hfperclk(), 10_500_000);
+//! ```
+use efm32::CMU;
+
+/// HFRCO frequency band
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HfrcoBand {
+    /// 1.2 MHz
+    MHz1,
+    /// 6.6 MHz
+    MHz7,
+    /// 11 MHz
+    MHz11,
+    /// 14 MHz, the reset default
+    MHz14,
+    /// 21 MHz
+    MHz21,
+}
+
+impl HfrcoBand {
+    /// Nominal frequency in Hz.
+    pub fn hz(self) -> u32 {
+        match self {
+            HfrcoBand::MHz1 => 1_200_000,
+            HfrcoBand::MHz7 => 6_600_000,
+            HfrcoBand::MHz11 => 11_000_000,
+            HfrcoBand::MHz14 => 14_000_000,
+            HfrcoBand::MHz21 => 21_000_000,
+        }
+    }
+
+    /// `CMU_HFRCOCTRL.BAND` value.
+    fn bits(self) -> u32 {
+        match self {
+            HfrcoBand::MHz1 => 0,
+            HfrcoBand::MHz7 => 1,
+            HfrcoBand::MHz11 => 2,
+            HfrcoBand::MHz14 => 3,
+            HfrcoBand::MHz21 => 4,
+        }
+    }
+
+    /// Factory tuning value for the band, from DEVINFO.
+    fn tuning(self) -> u32 {
+        let devinfo = unsafe { &*efm32::DEVINFO::ptr() };
+        let cal0 = devinfo.hfrcocal0.read().bits();
+
+        match self {
+            HfrcoBand::MHz1 => cal0 & 0xff,
+            HfrcoBand::MHz7 => (cal0 >> 8) & 0xff,
+            HfrcoBand::MHz11 => (cal0 >> 16) & 0xff,
+            HfrcoBand::MHz14 => (cal0 >> 24) & 0xff,
+            HfrcoBand::MHz21 => devinfo.hfrcocal1.read().bits() & 0xff,
+        }
+    }
+}
+
+/// USHFRCO frequency in its 48 MHz band, as needed by USB.
+pub const USHFRCO_HZ: u32 = 48_000_000;
+
+/// HFCLK source
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HfClockSource {
+    /// High frequency RC oscillator
+    Hfrco(HfrcoBand),
+    /// USB high frequency RC oscillator divided by 2 (24 MHz)
+    UshfrcoDiv2,
+}
+
+impl HfClockSource {
+    /// HFCLK frequency in Hz.
+    pub fn hz(self) -> u32 {
+        match self {
+            HfClockSource::Hfrco(band) => band.hz(),
+            HfClockSource::UshfrcoDiv2 => USHFRCO_HZ / 2,
+        }
+    }
+}
+
+/// Tune USHFRCO to its 48 MHz band with factory calibration from DEVINFO.
+fn ushfrco_48mhz(cmu: &CMU) {
+    let devinfo = unsafe { &*efm32::DEVINFO::ptr() };
+    let cal = devinfo.ushfrcocal0.read().bits();
+    let tuning = (cal >> 16) & 0x7f;
+    let fine_tuning = (cal >> 24) & 0x3f;
+
+    // BAND = 1 (48 MHz), divider by 2 enabled (USHFRCODIV2DIS cleared).
+    cmu.ushfrcoconf.write(|w| unsafe { w.bits(1) });
+    cmu.ushfrcoctrl.modify(|r, w| unsafe { w.bits((r.bits() & !0x7f) | tuning) });
+    cmu.ushfrcotune.modify(|r, w| unsafe { w.bits((r.bits() & !0x3f) | fine_tuning) });
+}
+
+/// HFCORECLK / HFPERCLK prescaler
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Prescaler {
+    Div1,
+    Div2,
+    Div4,
+    Div8,
+    Div16,
+    Div32,
+    Div64,
+    Div128,
+    Div256,
+    Div512,
+}
+
+impl Prescaler {
+    /// Division factor.
+    pub fn divisor(self) -> u32 {
+        1 << self.bits()
+    }
+
+    /// `CMU_HFCORECLKDIV` / `CMU_HFPERCLKDIV` value, log2 of the divisor.
+    fn bits(self) -> u8 {
+        self as u8
+    }
+}
+
+/// Flash needs a wait state above this HFCORECLK frequency.
+const MAX_FREQ_WS0: u32 = 16_000_000;
+
+/// Clock tree configuration, applied by `freeze`
+#[derive(Debug, Clone, Copy)]
+pub struct Config {
+    source: HfClockSource,
+    hfcore_prescaler: Prescaler,
+    hfper_prescaler: Prescaler,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Config {
+    /// Reset configuration: HFRCO at 14 MHz, no prescalers.
+    pub const fn new() -> Self {
+        Config {
+            source: HfClockSource::Hfrco(HfrcoBand::MHz14),
+            hfcore_prescaler: Prescaler::Div1,
+            hfper_prescaler: Prescaler::Div1,
+        }
+    }
+
+    /// Run HFCLK from HFRCO, tuned to `band`.
+    pub fn hfrco(mut self, band: HfrcoBand) -> Self {
+        self.source = HfClockSource::Hfrco(band);
+        self
+    }
+
+    /// Run HFCLK from USHFRCO divided by 2 (24 MHz), as required by USB.
+    ///
+    /// USHFRCO is tuned to 48 MHz, and clock recovery against USB
+    /// start-of-frame packets is enabled.
+    pub fn ushfrco(mut self) -> Self {
+        self.source = HfClockSource::UshfrcoDiv2;
+        self
+    }
+
+    /// Divide HFCLK for the core, DMA and USB (HFCORECLK).
+    pub fn hfcore_prescaler(mut self, prescaler: Prescaler) -> Self {
+        self.hfcore_prescaler = prescaler;
+        self
+    }
+
+    /// Divide HFCLK for peripherals (HFPERCLK), i.e. USART, TIMER, ADC...
+    pub fn hfper_prescaler(mut self, prescaler: Prescaler) -> Self {
+        self.hfper_prescaler = prescaler;
+        self
+    }
+
+    /// Apply the configuration and return resulting frequencies.
+    ///
+    /// Taking `CMU` by reference makes sure the caller owns it, so the
+    /// clock tree can't be changed behind `Clocks` back.
+    pub fn freeze(self, cmu: &CMU) -> Clocks {
+        let msc = unsafe { &*efm32::MSC::ptr() };
+
+        let hfclk = self.source.hz();
+        let hfcoreclk = hfclk / self.hfcore_prescaler.divisor();
+        let hfperclk = hfclk / self.hfper_prescaler.divisor();
+
+        critical_section::with(|_| {
+            // Go slow on flash while switching, whatever the old and new clocks are.
+            msc.readctrl.modify(|_, w| w.mode().ws1());
+
+            match self.source {
+                HfClockSource::Hfrco(band) => {
+                    cmu.oscencmd.write(|w| w.hfrcoen().set_bit());
+                    while cmu.status.read().hfrcordy().bit_is_clear() {}
+
+                    cmu.hfrcoctrl.modify(|r, w| unsafe {
+                        w.bits((r.bits() & !0x7ff) | (band.bits() << 8) | band.tuning())
+                    });
+                    cmu.cmd.write(|w| w.hfclksel().hfrco());
+                }
+                HfClockSource::UshfrcoDiv2 => {
+                    ushfrco_48mhz(cmu);
+                    cmu.oscencmd.write(|w| w.ushfrcoen().set_bit());
+                    while cmu.status.read().ushfrcordy().bit_is_clear() {}
+
+                    // USB core clock from USHFRCO as well, so clock recovery
+                    // sees start-of-frame packets once USB is enabled.
+                    cmu.cmd.write(|w| w.usbcclksel().ushfrco());
+                    while cmu.status.read().usbcushfrcosel().bit_is_clear() {}
+                    cmu.usbcrctrl.write(|w| w.en().set_bit());
+
+                    cmu.cmd.write(|w| w.hfclksel().ushfrcodiv2());
+                }
+            }
+
+            cmu.hfcoreclkdiv.modify(|_, w| unsafe {
+                w.hfcoreclkdiv().bits(self.hfcore_prescaler.bits())
+            });
+            cmu.hfperclkdiv.modify(|_, w| unsafe {
+                w.hfperclken()
+                    .set_bit()
+                    .hfperclkdiv()
+                    .bits(self.hfper_prescaler.bits())
+            });
+
+            if hfcoreclk <= MAX_FREQ_WS0 {
+                msc.readctrl.modify(|_, w| w.mode().ws0());
+            }
+        });
+
+        Clocks {
+            source: self.source,
+            hfclk,
+            hfcoreclk,
+            hfperclk,
+        }
+    }
+}
+
+/// Frozen clock frequencies
+#[derive(Debug, Clone, Copy)]
+pub struct Clocks {
+    source: HfClockSource,
+    hfclk: u32,
+    hfcoreclk: u32,
+    hfperclk: u32,
+}
+
+impl Clocks {
+    /// HFCLK source.
+    pub fn source(&self) -> HfClockSource {
+        self.source
+    }
+
+    /// HFCLK frequency in Hz.
+    pub fn hfclk(&self) -> u32 {
+        self.hfclk
+    }
+
+    /// Core clock (HFCORECLK) frequency in Hz, also clocks USB and SysTick.
+    pub fn hfcoreclk(&self) -> u32 {
+        self.hfcoreclk
+    }
+
+    /// Peripheral clock (HFPERCLK) frequency in Hz.
+    pub fn hfperclk(&self) -> u32 {
+        self.hfperclk
+    }
+
+    /// Whether USHFRCO is tuned by USB clock recovery.
+    pub fn usb_clock_recovery(&self) -> bool {
+        self.source == HfClockSource::UshfrcoDiv2
+    }
+}
+
+/// `USB_DSTS` register, in the OTG core.
+const USB_DSTS: usize = crate::usb::USB_CORE_BASE + 0x808;
+
+/// Start-of-frame packets seen before USHFRCO is considered locked.
+const LOCK_FRAMES: u16 = 8;
+
+/// USB clock recovery status
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecoveryStatus {
+    /// Clock recovery is not enabled, or USB core is not clocked
+    Disabled,
+    /// Waiting for start-of-frame packets, e.g. bus is suspended or not
+    /// connected yet. USHFRCO runs from its factory calibration meanwhile.
+    Searching,
+    /// USHFRCO has been tuned against enough start-of-frame packets
+    Locked,
+}
+
+/// Tracks USHFRCO clock recovery
+///
+/// Clock recovery adjusts USHFRCO on every start-of-frame packet (1 ms),
+/// there's no lock flag in hardware. Instead the frame number is followed,
+/// and recovery is considered locked after a few frames without the bus
+/// going into suspend. Poll it along with the USB device:
+///
+/// ``` no_run
+/// # use tomu::clocks::{ClockRecovery, RecoveryStatus};
+/// let mut recovery = ClockRecovery::new();
+///
+/// loop {
+///     // usb_dev.poll(...);
+///     if recovery.poll() == RecoveryStatus::Locked {
+///         // Safe to rely on USHFRCO accuracy, e.g. for UART baudrates.
+///     }
+/// }
+/// ```
+pub struct ClockRecovery {
+    last_frame: Option<u16>,
+    frames: u16,
+}
+
+impl Default for ClockRecovery {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ClockRecovery {
+    pub const fn new() -> Self {
+        ClockRecovery {
+            last_frame: None,
+            frames: 0,
+        }
+    }
+
+    /// Update frame count and return clock recovery status.
+    pub fn poll(&mut self) -> RecoveryStatus {
+        let cmu = unsafe { &*efm32::CMU::ptr() };
+
+        if cmu.usbcrctrl.read().en().bit_is_clear()
+            || cmu.hfcoreclken0.read().usbc().bit_is_clear()
+        {
+            self.last_frame = None;
+            self.frames = 0;
+            return RecoveryStatus::Disabled;
+        }
+
+        let dsts = unsafe { core::ptr::read_volatile(USB_DSTS as *const u32) };
+        let suspended = dsts & 1 != 0;
+        // SOFFN, 14 bits frame number of the last start-of-frame.
+        let frame = ((dsts >> 8) & 0x3fff) as u16;
+
+        if suspended {
+            self.last_frame = None;
+            self.frames = 0;
+            return RecoveryStatus::Searching;
+        }
+
+        if let Some(last) = self.last_frame {
+            let elapsed = frame.wrapping_sub(last) & 0x3fff;
+            self.frames = self.frames.saturating_add(elapsed);
+        }
+        self.last_frame = Some(frame);
+
+        if self.frames >= LOCK_FRAMES {
+            RecoveryStatus::Locked
+        } else {
+            RecoveryStatus::Searching
+        }
+    }
+}
+
+/// Low frequency oscillator
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LfOscillator {
+    /// Low frequency RC oscillator (32.768 kHz)
+    Lfrco,
+    /// Low frequency crystal oscillator (32.768 kHz), needs an external crystal
+    Lfxo,
+    /// Ultra low frequency RC oscillator (1 kHz), always running, even in EM3
+    Ulfrco,
+}
+
+impl LfOscillator {
+    /// Oscillator frequency in Hz.
+    pub fn hz(self) -> u32 {
+        match self {
+            LfOscillator::Lfrco | LfOscillator::Lfxo => 32_768,
+            LfOscillator::Ulfrco => 1_000,
+        }
+    }
+
+    /// Enable the oscillator and wait until it's ready.
+    fn enable(self, cmu: &CMU) {
+        match self {
+            LfOscillator::Lfrco => {
+                cmu.oscencmd.write(|w| w.lfrcoen().set_bit());
+                while cmu.status.read().lfrcordy().bit_is_clear() {}
+            }
+            LfOscillator::Lfxo => {
+                cmu.oscencmd.write(|w| w.lfxoen().set_bit());
+                while cmu.status.read().lfxordy().bit_is_clear() {}
+            }
+            LfOscillator::Ulfrco => {}
+        }
+    }
+
+    /// `CMU_LFCLKSEL` LFA/LFB value and whether the extended (ULFRCO) bit is set.
+    fn lfclksel(self) -> (u32, bool) {
+        match self {
+            LfOscillator::Lfrco => (1, false),
+            LfOscillator::Lfxo => (2, false),
+            LfOscillator::Ulfrco => (0, true),
+        }
+    }
+}
+
+/// Low frequency clock configuration, applied by `freeze`
+#[derive(Debug, Clone, Copy)]
+pub struct LfConfig {
+    lfa: Option<LfOscillator>,
+    lfb: Option<LfOscillator>,
+    wdog: Option<LfOscillator>,
+    rtc_prescaler: u16,
+    leuart0_prescaler: u8,
+}
+
+impl Default for LfConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl LfConfig {
+    /// Nothing routed, low frequency domain stays off.
+    pub const fn new() -> Self {
+        LfConfig {
+            lfa: None,
+            lfb: None,
+            wdog: None,
+            rtc_prescaler: 1,
+            leuart0_prescaler: 1,
+        }
+    }
+
+    /// Clock LFA, and RTC, from `source`.
+    pub fn lfa(mut self, source: LfOscillator) -> Self {
+        self.lfa = Some(source);
+        self
+    }
+
+    /// Clock LFB, and LEUART0, from `source`.
+    pub fn lfb(mut self, source: LfOscillator) -> Self {
+        self.lfb = Some(source);
+        self
+    }
+
+    /// Run `source` for the watchdog, see `watchdog::Watchdog`.
+    pub fn wdog(mut self, source: LfOscillator) -> Self {
+        self.wdog = Some(source);
+        self
+    }
+
+    /// Divide LFA for RTC, `div` is a power of 2 up to 32768.
+    pub fn rtc_prescaler(mut self, div: u16) -> Self {
+        assert!(div.is_power_of_two());
+        self.rtc_prescaler = div;
+        self
+    }
+
+    /// Divide LFB for LEUART0, `div` is 1, 2, 4 or 8.
+    pub fn leuart0_prescaler(mut self, div: u8) -> Self {
+        assert!(div.is_power_of_two() && div <= 8);
+        self.leuart0_prescaler = div;
+        self
+    }
+
+    /// Enable oscillators, route LFA/LFB, set prescalers and gate
+    /// clocks to the peripherals.
+    pub fn freeze(self, cmu: &CMU) -> LfClocks {
+        critical_section::with(|_| {
+            if self.lfa.is_some() || self.lfb.is_some() {
+                cmu.hfcoreclken0.modify(|_, w| w.le().set_bit());
+            }
+
+            for source in [self.lfa, self.lfb, self.wdog].iter().flatten() {
+                source.enable(cmu);
+            }
+
+            if let Some(source) = self.lfa {
+                let (sel, ext) = source.lfclksel();
+                cmu.lfclksel.modify(|r, w| unsafe {
+                    w.bits((r.bits() & !(0x3 | (1 << 16))) | sel | ((ext as u32) << 16))
+                });
+
+                let presc = self.rtc_prescaler.trailing_zeros();
+                cmu.lfapresc0.modify(|r, w| unsafe { w.bits((r.bits() & !0xf) | presc) });
+                sync(cmu);
+                cmu.lfaclken0.modify(|_, w| w.rtc().set_bit());
+                sync(cmu);
+            }
+
+            if let Some(source) = self.lfb {
+                let (sel, ext) = source.lfclksel();
+                cmu.lfclksel.modify(|r, w| unsafe {
+                    w.bits((r.bits() & !((0x3 << 2) | (1 << 20))) | (sel << 2) | ((ext as u32) << 20))
+                });
+
+                let presc = u32::from(self.leuart0_prescaler).trailing_zeros();
+                cmu.lfbpresc0.modify(|r, w| unsafe { w.bits((r.bits() & !0x3) | presc) });
+                sync(cmu);
+                cmu.lfbclken0.modify(|_, w| w.leuart0().set_bit());
+                sync(cmu);
+            }
+        });
+
+        LfClocks {
+            rtc: self.lfa.map(|source| RtcClock {
+                source,
+                hz: source.hz() / u32::from(self.rtc_prescaler),
+            }),
+            leuart0: self.lfb.map(|source| Leuart0Clock {
+                source,
+                hz: source.hz() / u32::from(self.leuart0_prescaler),
+            }),
+            wdog: self.wdog.map(|source| WdogClock { source }),
+        }
+    }
+}
+
+/// Wait until writes to LF clock registers have been synchronized.
+fn sync(cmu: &CMU) {
+    while cmu.syncbusy.read().bits() != 0 {}
+}
+
+/// Frozen low frequency clocks, one token per low energy peripheral
+/// whose clock is running. Take a token out to hand it to its driver.
+pub struct LfClocks {
+    pub rtc: Option<RtcClock>,
+    pub leuart0: Option<Leuart0Clock>,
+    /// Taken by `Tomu::builder()` for `Tomu::watchdog`
+    pub wdog: Option<WdogClock>,
+}
+
+macro_rules! lf_clock {
+    ($($(#[$doc:meta])* $Clock:ident,)+) => {
+        $(
+            $(#[$doc])*
+            #[derive(Debug)]
+            pub struct $Clock {
+                source: LfOscillator,
+                hz: u32,
+            }
+
+            impl $Clock {
+                /// Oscillator the clock is derived from.
+                pub fn source(&self) -> LfOscillator {
+                    self.source
+                }
+
+                /// Clock frequency in Hz, after prescaler.
+                pub fn hz(&self) -> u32 {
+                    self.hz
+                }
+            }
+        )+
+    }
+}
+
+lf_clock! {
+    /// RTC clock, from LFA
+    RtcClock,
+    /// LEUART0 clock, from LFB
+    Leuart0Clock,
+}
+
+/// Watchdog oscillator, running
+#[derive(Debug)]
+pub struct WdogClock {
+    source: LfOscillator,
+}
+
+impl WdogClock {
+    /// ULFRCO, which is always running, and the watchdog's reset clock.
+    pub(crate) fn ulfrco() -> Self {
+        WdogClock {
+            source: LfOscillator::Ulfrco,
+        }
+    }
+
+    /// Oscillator to select in `WDOG_CTRL.CLKSEL`.
+    pub fn source(&self) -> LfOscillator {
+        self.source
+    }
+
+    /// Oscillator frequency in Hz.
+    pub fn hz(&self) -> u32 {
+        self.source.hz()
+    }
+}
@@ -0,0 +1,16 @@
+//! CRC-32, as used by the settings store and `update::Update::finish`
+/// CRC-32 (IEEE 802.3), same as zlib, continuing from `crc` (0 to start).
+pub fn crc32(crc: u32, bytes: &[u8]) -> u32 {
+    let mut crc = !crc;
+    for byte in bytes {
+        crc ^= u32::from(*byte);
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xedb8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
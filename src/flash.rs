@@ -0,0 +1,240 @@
+//! Flash (MSC) support for tomu
+//!
+//! `Flash` gives access to the persistent data region reserved by the
+//! `persistent-data` feature (see `layout`), through `embedded-storage`
+//! `NorFlash` traits. Offsets are relative to the start of the region, so
+//! code using it doesn't need to care where the region is placed.
+//!
+//! Writes and erases are checked against toboot sectors and the running
+//! image regardless of the region, so a wrong layout can't brick the device.
+//!
+//! ``` no_run
+//! # use tomu::flash::Flash;
+//! use embedded_storage::nor_flash::{NorFlash, ReadNorFlash};
+//!
+//! # let p = tomu::efm32hg::Peripherals::take().unwrap();
+//! let mut flash = Flash::new(p.MSC);
+//!
+//! flash.erase(0, 1024).unwrap();
+//! flash.write(0, &[0xca, 0xfe, 0xba, 0xbe]).unwrap();
+//!
+//! let mut buf = [0u8; 4];
+//! flash.read(0, &mut buf).unwrap();
+//! ```
+use core::ops::Range;
+
+use efm32::MSC;
+use embedded_storage::nor_flash::{
+    check_erase, check_read, check_write, ErrorType, NorFlash, NorFlashError, NorFlashErrorKind,
+    ReadNorFlash,
+};
+
+use crate::layout::{self, PAGE_SIZE, TOBOOT_SECTORS};
+
+/// Writing this to `MSC_LOCK` unlocks MSC registers.
+const MSC_UNLOCK_CODE: u32 = 0x1b71;
+
+/// Flash error
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// Offset or length is not aligned to `WRITE_SIZE` or `ERASE_SIZE`
+    NotAligned,
+    /// Access is outside of the region
+    OutOfBounds,
+    /// Access would touch toboot sectors or the running image
+    Protected,
+    /// MSC refused to write, the page is locked
+    Locked,
+    /// MSC refused to write, the address is invalid
+    InvalidAddress,
+}
+
+impl NorFlashError for Error {
+    fn kind(&self) -> NorFlashErrorKind {
+        match self {
+            Error::NotAligned => NorFlashErrorKind::NotAligned,
+            Error::OutOfBounds => NorFlashErrorKind::OutOfBounds,
+            Error::Protected | Error::Locked | Error::InvalidAddress => NorFlashErrorKind::Other,
+        }
+    }
+}
+
+impl From<NorFlashErrorKind> for Error {
+    fn from(kind: NorFlashErrorKind) -> Self {
+        match kind {
+            NorFlashErrorKind::NotAligned => Error::NotAligned,
+            _ => Error::OutOfBounds,
+        }
+    }
+}
+
+/// Flash region accessed through MSC
+pub struct Flash {
+    msc: MSC,
+    region: Range<u32>,
+}
+
+impl Flash {
+    /// Take `MSC` and give access to the persistent data region.
+    ///
+    /// Without `persistent-data` feature the region is empty,
+    /// and every access fails with `Error::OutOfBounds`.
+    pub fn new(msc: MSC) -> Self {
+        Self::with_region(msc, layout::PERSISTENT_START..layout::PERSISTENT_END)
+    }
+
+    /// Access arbitrary page-aligned `region`, still guarded against
+    /// toboot sectors and the running image.
+    pub(crate) fn with_region(msc: MSC, region: Range<u32>) -> Self {
+        debug_assert!(
+            region.start.is_multiple_of(PAGE_SIZE) && region.end.is_multiple_of(PAGE_SIZE)
+        );
+
+        Flash { msc, region }
+    }
+
+    /// Absolute address range of the region.
+    pub fn region(&self) -> Range<u32> {
+        self.region.clone()
+    }
+
+    /// Release the underlying `MSC` peripheral.
+    pub fn free(self) -> MSC {
+        self.msc
+    }
+
+    /// Check absolute address range `[from, to)` against toboot sectors and the running image.
+    fn check_protected(&self, from: u32, to: u32) -> Result<(), Error> {
+        if layout::BOOTLOADER && from < TOBOOT_SECTORS * PAGE_SIZE {
+            return Err(Error::Protected);
+        }
+
+        if from < image_end() && to > layout::APP_START {
+            return Err(Error::Protected);
+        }
+
+        Ok(())
+    }
+
+    /// Unlock MSC and enable writing for the duration of `f`.
+    fn unlocked<T>(&mut self, f: impl FnOnce(&MSC) -> Result<T, Error>) -> Result<T, Error> {
+        critical_section::with(|_| {
+            self.msc.lock.write(|w| unsafe { w.bits(MSC_UNLOCK_CODE) });
+            self.msc.writectrl.modify(|_, w| w.wren().set_bit());
+
+            let result = f(&self.msc);
+
+            self.msc.writectrl.modify(|_, w| w.wren().clear_bit());
+            self.msc.lock.write(|w| unsafe { w.bits(0) });
+
+            result
+        })
+    }
+}
+
+/// End of the running image in flash.
+pub(crate) fn image_end() -> u32 {
+    extern "C" {
+        // Defined by cortex-m-rt, load address and size of .data,
+        // which is the last part of the image in flash.
+        static __sidata: u32;
+        static __sdata: u32;
+        static __edata: u32;
+    }
+
+    unsafe {
+        let sidata = core::ptr::addr_of!(__sidata) as u32;
+        let sdata = core::ptr::addr_of!(__sdata) as u32;
+        let edata = core::ptr::addr_of!(__edata) as u32;
+        sidata + (edata - sdata)
+    }
+}
+
+/// Load `address` into MSC, and check whether it can be written.
+fn load_address(msc: &MSC, address: u32) -> Result<(), Error> {
+    msc.addrb.write(|w| unsafe { w.bits(address) });
+    msc.writecmd.write(|w| w.laddrim().set_bit());
+
+    let status = msc.status.read();
+    if status.invaddr().bit_is_set() {
+        return Err(Error::InvalidAddress);
+    }
+    if status.locked().bit_is_set() {
+        return Err(Error::Locked);
+    }
+
+    Ok(())
+}
+
+fn wait_busy(msc: &MSC) {
+    while msc.status.read().busy().bit_is_set() {}
+}
+
+impl ErrorType for Flash {
+    type Error = Error;
+}
+
+impl ReadNorFlash for Flash {
+    const READ_SIZE: usize = 1;
+
+    fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Error> {
+        check_read(self, offset, bytes.len())?;
+
+        let address = (self.region.start + offset) as *const u8;
+        for (i, byte) in bytes.iter_mut().enumerate() {
+            *byte = unsafe { core::ptr::read_volatile(address.add(i)) };
+        }
+
+        Ok(())
+    }
+
+    fn capacity(&self) -> usize {
+        (self.region.end - self.region.start) as usize
+    }
+}
+
+impl NorFlash for Flash {
+    const WRITE_SIZE: usize = 4;
+
+    const ERASE_SIZE: usize = PAGE_SIZE as usize;
+
+    fn erase(&mut self, from: u32, to: u32) -> Result<(), Error> {
+        check_erase(self, from, to)?;
+
+        let from = self.region.start + from;
+        let to = self.region.start + to;
+        self.check_protected(from, to)?;
+
+        self.unlocked(|msc| {
+            for page in (from..to).step_by(PAGE_SIZE as usize) {
+                load_address(msc, page)?;
+                msc.writecmd.write(|w| w.erasepage().set_bit());
+                wait_busy(msc);
+            }
+
+            Ok(())
+        })
+    }
+
+    fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Error> {
+        check_write(self, offset, bytes.len())?;
+
+        let from = self.region.start + offset;
+        self.check_protected(from, from + bytes.len() as u32)?;
+
+        self.unlocked(|msc| {
+            for (i, word) in bytes.chunks_exact(4).enumerate() {
+                load_address(msc, from + 4 * i as u32)?;
+
+                let word = u32::from_le_bytes([word[0], word[1], word[2], word[3]]);
+
+                while msc.status.read().wdataready().bit_is_clear() {}
+                msc.wdata.write(|w| unsafe { w.bits(word) });
+                msc.writecmd.write(|w| w.writeonce().set_bit());
+                wait_busy(msc);
+            }
+
+            Ok(())
+        })
+    }
+}
@@ -0,0 +1,18 @@
+//! Flash layout the application is linked with
+//!
+//! `build.rs` generates `memory.x` and these constants from the same settings:
+//!
+//! - `no-bootloader` feature links the application at 0x0, for boards flashed
+//!   over SWD without toboot.
+//! - `TOMU_START_PAGE` environment variable moves the application start page
+//!   (16 by default, the first page after toboot).
+//! - `persistent-data` feature reserves the last `TOMU_PERSISTENT_PAGES`
+//!   (2 by default) flash pages for data that survives firmware updates.
+//!
+//! Environment variables can be set in `.cargo/config` `[env]` section
+//! so every build of the application agrees on the layout.
+//!
+//! Flash geometry (`FLASH_SIZE`, `PAGE_SIZE`, `TOBOOT_SECTORS`) is fixed by
+//! the chip and toboot, it's here so the rest of the crate has one place to
+//! take it from.
+include!(concat!(env!("OUT_DIR"), "/layout.rs"));
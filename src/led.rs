@@ -1,3 +1,14 @@
+//! On-board and external leds
+//!
+//! The on-board leds are active low, green on PA0 and red on PB7, both in
+//! `Tomu::leds`. They sit on timer compare outputs (green on TIMER0, red on
+//! TIMER1), `PwmGreenLED` / `PwmRedLED` dim them with hardware PWM, gamma
+//! corrected so fading looks smooth. Leds wired to the free pins can be
+//! wrapped in `LED<PIN, ActiveHigh>` (or `ActiveLow`). All of them implement
+//! `LedTrait`, which `Pattern` drives without blocking on a delay.
+use core::marker::PhantomData;
+
+use efm32::{TIMER0, TIMER1};
 use efm32_hal::gpio::{
     pins::{PA0, PB7},
     Normal, OpenDrain, Output, PullUp,
@@ -5,11 +16,73 @@ use efm32_hal::gpio::{
 use embedded_hal::digital::v2::OutputPin;
 #[cfg(feature = "unproven")]
 use embedded_hal::digital::v2::ToggleableOutputPin;
+use embedded_hal::timer::CountDown;
+
+use crate::clocks::Clocks;
+use crate::timer::{Channel, Hertz, Timer};
+
+mod pattern;
+pub use pattern::Pattern;
 
-pub struct LED<T>(T)
+/// Led driven by an output pin, `P` tells which level turns it on.
+///
+/// On-board leds are active low, which is the default. Leds wired to the
+/// free pins can be either, e.g. one sourced from PC0 through a resistor:
+///
+/// ``` no_run
+/// use embedded_hal::digital::v2::OutputPin;
+/// use tomu::led::{ActiveHigh, LED};
+///
+/// fn status_led<PIN: OutputPin>(pin: PIN) -> LED<PIN, ActiveHigh> {
+///     LED::new(pin)
+/// }
+/// ```
+pub struct LED<T, P = ActiveLow>(PhantomData<P>, T)
 where
     T: ?Sized;
 
+/// Led polarity, implemented by `ActiveLow` and `ActiveHigh`
+pub trait Polarity {
+    /// Pin level that turns the led on.
+    const ACTIVE_HIGH: bool;
+}
+
+/// Led is on when the pin is low, e.g. sinked into the pin
+pub struct ActiveLow;
+
+/// Led is on when the pin is high, e.g. sourced from the pin
+pub struct ActiveHigh;
+
+impl Polarity for ActiveLow {
+    const ACTIVE_HIGH: bool = false;
+}
+
+impl Polarity for ActiveHigh {
+    const ACTIVE_HIGH: bool = true;
+}
+
+impl<T: OutputPin, P: Polarity> LED<T, P> {
+    /// Take `pin` as a led, initially off.
+    pub fn new(pin: T) -> Self {
+        let mut led = LED(PhantomData, pin);
+        led.set(false);
+        led
+    }
+
+    /// Release the pin.
+    pub fn free(self) -> T {
+        self.1
+    }
+
+    fn set(&mut self, on: bool) {
+        let _ = if on == P::ACTIVE_HIGH {
+            self.1.set_high()
+        } else {
+            self.1.set_low()
+        };
+    }
+}
+
 /// Public trait for leds, All leds can have common behavior
 /// that it can be turned on, and turned off. This can be used
 /// to set common pins as led type without having to care whether
@@ -43,34 +116,162 @@ impl LEDs {
         red: PB7<Output<OpenDrain<Normal, PullUp>>>,
     ) -> Self {
         LEDs {
-            green: LED(green),
-            red: LED(red),
+            green: LED(PhantomData, green),
+            red: LED(PhantomData, red),
         }
     }
 }
 
 #[cfg(not(feature = "unproven"))]
-impl<T: OutputPin> LedTrait for LED<T> {
+impl<T: OutputPin, P: Polarity> LedTrait for LED<T, P> {
     fn on(&mut self) {
-        let _ = self.0.set_low();
+        self.set(true);
     }
 
     fn off(&mut self) {
-        let _ = self.0.set_high();
+        self.set(false);
     }
 }
 
 #[cfg(feature = "unproven")]
-impl<T: OutputPin + ToggleableOutputPin> LedTrait for LED<T> {
+impl<T: OutputPin + ToggleableOutputPin, P: Polarity> LedTrait for LED<T, P> {
     fn on(&mut self) {
-        let _ = self.0.set_low();
+        self.set(true);
     }
 
     fn off(&mut self) {
-        let _ = self.0.set_high();
+        self.set(false);
     }
 
     fn toggle(&mut self) {
-        let _ = self.0.toggle();
+        let _ = self.1.toggle();
+    }
+}
+
+/// PWM frequency, high enough not to flicker.
+const PWM_HZ: u32 = 200;
+
+/// Gamma 2.2 correction, from 8-bit brightness to 16-bit duty cycle.
+static GAMMA: [u16; 256] = [
+    0, 0, 2, 4, 7, 11, 17, 24,
+    32, 42, 53, 65, 79, 94, 111, 129,
+    148, 169, 192, 216, 242, 270, 299, 330,
+    362, 396, 432, 469, 508, 549, 591, 635,
+    681, 729, 779, 830, 883, 938, 995, 1053,
+    1113, 1175, 1239, 1305, 1373, 1443, 1514, 1587,
+    1663, 1740, 1819, 1900, 1983, 2068, 2155, 2243,
+    2334, 2427, 2521, 2618, 2717, 2817, 2920, 3024,
+    3131, 3240, 3350, 3463, 3578, 3694, 3813, 3934,
+    4057, 4182, 4309, 4438, 4570, 4703, 4838, 4976,
+    5115, 5257, 5401, 5547, 5695, 5845, 5998, 6152,
+    6309, 6468, 6629, 6792, 6957, 7124, 7294, 7466,
+    7640, 7816, 7994, 8175, 8358, 8543, 8730, 8919,
+    9111, 9305, 9501, 9699, 9900, 10102, 10307, 10515,
+    10724, 10936, 11150, 11366, 11585, 11806, 12029, 12254,
+    12482, 12712, 12944, 13179, 13416, 13655, 13896, 14140,
+    14386, 14635, 14885, 15138, 15394, 15652, 15912, 16174,
+    16439, 16706, 16975, 17247, 17521, 17798, 18077, 18358,
+    18642, 18928, 19216, 19507, 19800, 20095, 20393, 20694,
+    20996, 21301, 21609, 21919, 22231, 22546, 22863, 23182,
+    23504, 23829, 24156, 24485, 24817, 25151, 25487, 25826,
+    26168, 26512, 26858, 27207, 27558, 27912, 28268, 28627,
+    28988, 29351, 29717, 30086, 30457, 30830, 31206, 31585,
+    31966, 32349, 32735, 33124, 33514, 33908, 34304, 34702,
+    35103, 35507, 35913, 36321, 36732, 37146, 37562, 37981,
+    38402, 38825, 39252, 39680, 40112, 40546, 40982, 41421,
+    41862, 42306, 42753, 43202, 43654, 44108, 44565, 45025,
+    45487, 45951, 46418, 46888, 47360, 47835, 48313, 48793,
+    49275, 49761, 50249, 50739, 51232, 51728, 52226, 52727,
+    53230, 53736, 54245, 54756, 55270, 55787, 56306, 56828,
+    57352, 57879, 58409, 58941, 59476, 60014, 60554, 61097,
+    61642, 62190, 62741, 63295, 63851, 64410, 64971, 65535,
+];
+
+/// LED dimmed with hardware PWM from a timer compare output
+///
+/// Green LED (PA0) is driven by TIMER0 CC0, red LED (PB7) by TIMER1 CC0.
+/// Brightness is gamma corrected, so it looks linear while fading:
+///
+/// ``` no_run
+/// # use tomu::led::{LedTrait, PwmGreenLED};
+/// # let p = tomu::efm32hg::Peripherals::take().unwrap();
+/// # let tomu = tomu::Tomu::from_parts(p.CMU, p.WDOG, p.GPIO, p.SYST);
+/// let mut green = PwmGreenLED::new(tomu.leds.green, p.TIMER0, &tomu.clocks);
+///
+/// green.set_brightness(64);
+/// green.off();
+/// ```
+pub struct PwmLED<T, TIM> {
+    led: LED<T>,
+    timer: Timer<TIM>,
+    brightness: u8,
+}
+
+pub type PwmGreenLED = PwmLED<PA0<Output<OpenDrain<Normal, PullUp>>>, TIMER0>;
+pub type PwmRedLED = PwmLED<PB7<Output<OpenDrain<Normal, PullUp>>>, TIMER1>;
+
+macro_rules! pwm_led {
+    ($($PIN:ident: ($TIMER:ident, $timer:ident, $location:expr),)+) => {
+        $(
+            impl PwmLED<$PIN<Output<OpenDrain<Normal, PullUp>>>, $TIMER> {
+                /// Drive `led` from `TIMER` CC0, starting off.
+                pub fn new(led: LED<$PIN<Output<OpenDrain<Normal, PullUp>>>>, timer: $TIMER, clocks: &Clocks) -> Self {
+                    let mut timer = Timer::$timer(timer, clocks);
+
+                    // LEDs are active low, pull the pin low until compare match.
+                    timer.enable_pwm(Channel::Cc0, true);
+                    timer.set_duty(Channel::Cc0, 0);
+                    timer.start(Hertz(PWM_HZ));
+                    timer.route(Channel::Cc0, $location, true);
+
+                    PwmLED { led, timer, brightness: 0 }
+                }
+
+                /// Set brightness, from 0 (off) to 255 (fully on).
+                pub fn set_brightness(&mut self, brightness: u8) {
+                    let period = u32::from(self.timer.top()) + 1;
+                    let duty = u32::from(GAMMA[usize::from(brightness)]) * period / 0xffff;
+
+                    self.timer.set_duty(Channel::Cc0, duty.min(0xffff) as u16);
+                    self.brightness = brightness;
+                }
+
+                /// Current brightness, as last set.
+                pub fn brightness(&self) -> u8 {
+                    self.brightness
+                }
+
+                /// Stop PWM and release the led and the timer.
+                pub fn free(mut self) -> (LED<$PIN<Output<OpenDrain<Normal, PullUp>>>>, $TIMER) {
+                    self.timer.route(Channel::Cc0, $location, false);
+
+                    (self.led, self.timer.free())
+                }
+            }
+
+            impl LedTrait for PwmLED<$PIN<Output<OpenDrain<Normal, PullUp>>>, $TIMER> {
+                fn on(&mut self) {
+                    self.set_brightness(u8::MAX);
+                }
+
+                fn off(&mut self) {
+                    self.set_brightness(0);
+                }
+
+                #[cfg(feature = "unproven")]
+                fn toggle(&mut self) {
+                    if self.brightness == 0 {
+                        self.on();
+                    } else {
+                        self.off();
+                    }
+                }
+            }
+        )+
     }
 }
+
+pwm_led! {
+    PA0: (TIMER0, timer0, 0),
+    PB7: (TIMER1, timer1, 3),
+}
@@ -0,0 +1,343 @@
+//! Non-blocking led patterns
+//!
+//! `Pattern` is a sequencer advanced by `tick`, called at a fixed rate from
+//! wherever is convenient: a SysTick, RTC or timer interrupt, or a main loop
+//! polling a `CountDown`. It doesn't own the led nor wait on a delay, so
+//! anything implementing `LedTrait` can be driven while the core does
+//! something else, or sleeps.
+//!
+//! ``` no_run
+//! # use tomu::led::Pattern;
+//! use embedded_hal::timer::CountDown;
+//!
+//! # let p = tomu::efm32hg::Peripherals::take().unwrap();
+//! # let mut tomu = tomu::Tomu::from_parts(p.CMU, p.WDOG, p.GPIO, p.SYST);
+//! let mut timer = tomu::timer::Timer::timer0(p.TIMER0, &tomu.clocks);
+//! timer.start(tomu::timer::Hertz(100));
+//!
+//! let mut pattern = Pattern::morse("SOS", 10);
+//! loop {
+//!     if timer.wait().is_ok() {
+//!         pattern.tick(&mut tomu.leds.red);
+//!     }
+//! }
+//! ```
+use super::LedTrait;
+
+/// Heartbeat: two short beats, then a pause (on, off, ... in ms).
+const HEARTBEAT: [u32; 4] = [100, 150, 100, 650];
+
+/// Blink code timings in ms.
+const BLINK_ON: u32 = 200;
+const BLINK_OFF: u32 = 300;
+const BLINK_PAUSE: u32 = 1500;
+
+/// Morse dot length in ms, everything else is a multiple of it.
+const MORSE_UNIT: u32 = 150;
+
+#[derive(Debug, Clone, Copy)]
+enum Kind<'a> {
+    Heartbeat,
+    BlinkCode(u8),
+    Morse(&'a str),
+}
+
+/// Led pattern sequencer
+#[derive(Debug, Clone)]
+pub struct Pattern<'a> {
+    kind: Kind<'a>,
+    tick_ms: u32,
+    step: usize,
+    remaining: u32,
+}
+
+impl<'a> Pattern<'a> {
+    const fn new(kind: Kind<'a>, tick_ms: u32) -> Self {
+        Pattern {
+            kind,
+            tick_ms,
+            step: 0,
+            remaining: 0,
+        }
+    }
+
+    /// Double beat, like a heart. `tick` is called every `tick_ms`.
+    pub const fn heartbeat(tick_ms: u32) -> Self {
+        Self::new(Kind::Heartbeat, tick_ms)
+    }
+
+    /// `count` blinks followed by a long pause, e.g. to tell error codes apart.
+    pub const fn blink_code(count: u8, tick_ms: u32) -> Self {
+        Self::new(Kind::BlinkCode(count), tick_ms)
+    }
+
+    /// `text` in morse code, letters and digits are sent, spaces separate
+    /// words and anything else is skipped. Repeats after a word gap, so
+    /// leading, trailing and repeated spaces are ignored.
+    pub const fn morse(text: &'a str, tick_ms: u32) -> Self {
+        Self::new(Kind::Morse(text), tick_ms)
+    }
+
+    /// Start over from the first step on the next `tick`.
+    pub fn restart(&mut self) {
+        self.step = 0;
+        self.remaining = 0;
+    }
+
+    /// Advance the pattern by one tick, switching `led` when a step ends.
+    pub fn tick<L: LedTrait + ?Sized>(&mut self, led: &mut L) {
+        if self.remaining == 0 {
+            let (on, ms) = match self.kind.step(self.step) {
+                Some(step) => step,
+                None => {
+                    self.step = 0;
+                    match self.kind.step(0) {
+                        Some(step) => step,
+                        None => {
+                            led.off();
+                            return;
+                        }
+                    }
+                }
+            };
+
+            if on {
+                led.on();
+            } else {
+                led.off();
+            }
+
+            self.step += 1;
+            self.remaining = ms.div_ceil(self.tick_ms.max(1)).max(1);
+        }
+
+        self.remaining -= 1;
+    }
+}
+
+impl Kind<'_> {
+    /// Led state and duration in ms of step `index`, `None` past the end.
+    fn step(&self, index: usize) -> Option<(bool, u32)> {
+        match *self {
+            Kind::Heartbeat => HEARTBEAT
+                .get(index)
+                .map(|&ms| (index.is_multiple_of(2), ms)),
+            Kind::BlinkCode(0) => (index == 0).then_some((false, BLINK_PAUSE)),
+            Kind::BlinkCode(count) => {
+                let blink = index / 2;
+                if blink >= usize::from(count) {
+                    None
+                } else if index.is_multiple_of(2) {
+                    Some((true, BLINK_ON))
+                } else if blink + 1 == usize::from(count) {
+                    Some((false, BLINK_PAUSE))
+                } else {
+                    Some((false, BLINK_OFF))
+                }
+            }
+            Kind::Morse(text) => morse_steps(text)
+                .nth(index)
+                .map(|(on, units)| (on, units * MORSE_UNIT)),
+        }
+    }
+}
+
+/// Dots and dashes of `c`, `None` if it can't be sent.
+fn morse_code(c: char) -> Option<&'static str> {
+    let code = match c.to_ascii_uppercase() {
+        'A' => ".-",
+        'B' => "-...",
+        'C' => "-.-.",
+        'D' => "-..",
+        'E' => ".",
+        'F' => "..-.",
+        'G' => "--.",
+        'H' => "....",
+        'I' => "..",
+        'J' => ".---",
+        'K' => "-.-",
+        'L' => ".-..",
+        'M' => "--",
+        'N' => "-.",
+        'O' => "---",
+        'P' => ".--.",
+        'Q' => "--.-",
+        'R' => ".-.",
+        'S' => "...",
+        'T' => "-",
+        'U' => "..-",
+        'V' => "...-",
+        'W' => ".--",
+        'X' => "-..-",
+        'Y' => "-.--",
+        'Z' => "--..",
+        '0' => "-----",
+        '1' => ".----",
+        '2' => "..---",
+        '3' => "...--",
+        '4' => "....-",
+        '5' => ".....",
+        '6' => "-....",
+        '7' => "--...",
+        '8' => "---..",
+        '9' => "----.",
+        _ => return None,
+    };
+
+    Some(code)
+}
+
+/// Flatten `text` into (on, units) steps: dot is 1 unit on, dash 3,
+/// symbols are separated by 1 unit off, letters by 3 and words by 7.
+fn morse_steps(text: &str) -> impl Iterator<Item = (bool, u32)> + '_ {
+    // Skipped characters go first, so they can't split a run of spaces.
+    let mut chars = text
+        .chars()
+        .filter(|&c| c == ' ' || morse_code(c).is_some())
+        .peekable();
+    let mut sent = false;
+
+    // Only spaces between two letters make a word gap, once per run, the
+    // gap after the last letter is the one repeating the text.
+    core::iter::from_fn(move || {
+        let c = chars.next()?;
+        let gap = c == ' ' && sent && chars.peek().is_some_and(|&next| next != ' ');
+        sent |= c != ' ';
+        Some((c, gap))
+    })
+    .flat_map(|(c, gap)| {
+        let letter = morse_code(c).into_iter().flat_map(|code| {
+            let last = code.len() - 1;
+            code.bytes().enumerate().flat_map(move |(i, symbol)| {
+                let on = if symbol == b'-' { 3 } else { 1 };
+                let off = if i == last { 3 } else { 1 };
+                [(true, on), (false, off)]
+            })
+        });
+
+        // Letter gap is already there, make it a word gap.
+        letter.chain(gap.then_some((false, 4)))
+    })
+    .chain(core::iter::once((false, 4)))
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use std::vec::Vec;
+
+    use super::*;
+
+    struct FakeLed(bool);
+
+    impl LedTrait for FakeLed {
+        fn on(&mut self) {
+            self.0 = true;
+        }
+
+        fn off(&mut self) {
+            self.0 = false;
+        }
+
+        #[cfg(feature = "unproven")]
+        fn toggle(&mut self) {
+            self.0 = !self.0;
+        }
+    }
+
+    /// Led state over `ticks` ticks, as (on, ticks) runs.
+    fn runs(mut pattern: Pattern, ticks: u32) -> Vec<(bool, u32)> {
+        let mut led = FakeLed(false);
+        let mut runs: Vec<(bool, u32)> = Vec::new();
+
+        for _ in 0..ticks {
+            pattern.tick(&mut led);
+            match runs.last_mut() {
+                Some((on, len)) if *on == led.0 => *len += 1,
+                _ => runs.push((led.0, 1)),
+            }
+        }
+
+        runs
+    }
+
+    #[test]
+    fn heartbeat() {
+        assert_eq!(
+            runs(Pattern::heartbeat(50), 2 * 20),
+            [
+                (true, 2),
+                (false, 3),
+                (true, 2),
+                (false, 13),
+                (true, 2),
+                (false, 3),
+                (true, 2),
+                (false, 13),
+            ]
+        );
+    }
+
+    #[test]
+    fn blink_code() {
+        assert_eq!(
+            runs(Pattern::blink_code(3, 100), 2 + 3 + 2 + 3 + 2 + 15 + 2),
+            [
+                (true, 2),
+                (false, 3),
+                (true, 2),
+                (false, 3),
+                (true, 2),
+                (false, 15),
+                (true, 2),
+            ]
+        );
+        assert_eq!(runs(Pattern::blink_code(0, 100), 40), [(false, 40)]);
+    }
+
+    #[test]
+    fn morse_timings() {
+        // Dot 1 unit, dash 3, symbol gap 1, letter gap 3, then a word gap.
+        assert_eq!(
+            runs(
+                Pattern::morse("AN", MORSE_UNIT),
+                1 + 1 + 3 + 3 + 3 + 1 + 1 + 7 + 1
+            ),
+            [
+                (true, 1),
+                (false, 1),
+                (true, 3),
+                (false, 3),
+                (true, 3),
+                (false, 1),
+                (true, 1),
+                (false, 7),
+                (true, 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn morse_tick_rounding() {
+        // 150ms units at 100ms ticks round up to 2 ticks.
+        assert_eq!(
+            runs(Pattern::morse("E", 100), 2 + 11),
+            [(true, 2), (false, 11)]
+        );
+    }
+
+    #[test]
+    fn morse_word_gap() {
+        let expected = [(true, 1), (false, 7), (true, 1), (false, 7), (true, 1)];
+
+        for text in ["E E", " E E", "E E ", "E  E", "E ! E", "E\u{e9} E"] {
+            assert_eq!(
+                runs(Pattern::morse(text, MORSE_UNIT), 17),
+                expected,
+                "{:?}",
+                text
+            );
+        }
+    }
+}
@@ -0,0 +1,247 @@
+//! Low energy UART (LEUART0) support for tomu
+//!
+//! LEUART0 runs from the low frequency B clock (LFBCLK), so unlike USART
+//! it keeps receiving while the core is in EM2 (deep sleep). Incoming data,
+//! start frames, or signal frames can be used to wake the core up.
+//!
+//! LEUART0 can be routed to PB13 (TX) / PB14 (RX), or PF0 (TX) / PF1 (RX).
+//! With a 32.768 kHz clock, the maximum baudrate is 9600.
+//!
+//! ``` no_run
+//! # use tomu::clocks::{LfConfig, LfOscillator};
+//! # use tomu::leuart::{Event, Serial};
+//! # let p = tomu::efm32hg::Peripherals::take().unwrap();
+//! let mut tomu = tomu::Tomu::builder()
+//!     .lf_clocks(LfConfig::new().lfb(LfOscillator::Lfrco))
+//!     .build(p.CMU, p.WDOG, p.GPIO, p.SYST);
+//!
+//! let mut serial = Serial::new(
+//!     p.LEUART0,
+//!     (tomu.gpio.pb13, tomu.gpio.pb14),
+//!     9600,
+//!     tomu.lf_clocks.leuart0.take().unwrap(),
+//! );
+//!
+//! // Ignore everything until `0x55` is received, then wake up on incoming data.
+//! serial.set_start_frame(0x55);
+//! serial.listen(Event::RxDataValid);
+//! ```
+use core::fmt;
+
+use efm32::LEUART0;
+use embedded_hal::serial;
+
+use crate::clocks::Leuart0Clock;
+pub use crate::uart::{Error, Pins};
+
+/// LEUART0 interrupt events
+pub enum Event {
+    /// Data is available in the receive buffer
+    RxDataValid,
+    /// Start frame has been received
+    StartFrame,
+    /// Signal frame has been received
+    SignalFrame,
+}
+
+/// Serial abstraction over LEUART0
+pub struct Serial<PINS> {
+    leuart: LEUART0,
+    pins: PINS,
+}
+
+/// Compute LEUART `CLKDIV` value, `CLKDIV = 256 * (fLEUART / baudrate - 1)`.
+fn clkdiv(lfbclk: u32, baudrate: u32) -> u32 {
+    let div = (256 * lfbclk + baudrate / 2) / baudrate;
+    div.saturating_sub(256) & 0x7ff8
+}
+
+impl<PINS: Pins<LEUART0>> Serial<PINS> {
+    /// Configure LEUART0 with the given `baudrate`, derived from `clock`.
+    ///
+    /// The clock token comes from `LfConfig::freeze` (or `Tomu::lf_clocks`),
+    /// which has already started the oscillator and routed it through LFB.
+    pub fn new(leuart: LEUART0, pins: PINS, baudrate: u32, clock: Leuart0Clock) -> Self {
+        pins.setup();
+
+        let serial = Serial { leuart, pins };
+
+        serial.sync();
+        serial.leuart.cmd.write(|w| {
+            w.rxdis().set_bit()
+             .txdis().set_bit()
+             .clearrx().set_bit()
+             .cleartx().set_bit()
+        });
+
+        // 8N1
+        serial.sync();
+        serial.leuart.ctrl.reset();
+
+        serial.sync();
+        serial.leuart.clkdiv
+            .write(|w| unsafe { w.bits(clkdiv(clock.hz(), baudrate)) });
+
+        serial.leuart.route.write(|w| unsafe {
+            w.rxpen().set_bit()
+             .txpen().set_bit()
+             .location().bits(PINS::LOCATION)
+        });
+
+        serial.leuart.ifc.write(|w| unsafe { w.bits(0xffff_ffff) });
+
+        serial.sync();
+        serial.leuart.cmd.write(|w| w.rxen().set_bit().txen().set_bit());
+
+        serial
+    }
+
+    /// Disable LEUART0 and release the peripheral and pins.
+    pub fn free(self) -> (LEUART0, PINS) {
+        self.sync();
+        self.leuart.cmd.write(|w| w.rxdis().set_bit().txdis().set_bit());
+        self.leuart.route.reset();
+
+        (self.leuart, self.pins)
+    }
+}
+
+impl<PINS> Serial<PINS> {
+    /// Wait until previous writes have been synchronized into the low frequency domain.
+    fn sync(&self) {
+        while self.leuart.syncbusy.read().bits() != 0 {}
+    }
+
+    /// Block the receiver until `frame` is received, everything received before
+    /// that is discarded. The start frame itself is loaded into the receive buffer.
+    pub fn set_start_frame(&mut self, frame: u8) {
+        self.sync();
+        self.leuart.startframe.write(|w| unsafe { w.startframe().bits(frame.into()) });
+
+        self.sync();
+        self.leuart.ctrl.modify(|_, w| w.sfubrx().set_bit());
+
+        self.sync();
+        self.leuart.cmd.write(|w| w.rxblocken().set_bit());
+    }
+
+    /// Stop blocking the receiver and stop waiting for start frame.
+    pub fn clear_start_frame(&mut self) {
+        self.sync();
+        self.leuart.ctrl.modify(|_, w| w.sfubrx().clear_bit());
+
+        self.sync();
+        self.leuart.cmd.write(|w| w.rxblockdis().set_bit());
+    }
+
+    /// Raise `Event::SignalFrame` whenever `frame` is received.
+    pub fn set_signal_frame(&mut self, frame: u8) {
+        self.sync();
+        self.leuart.sigframe.write(|w| unsafe { w.sigframe().bits(frame.into()) });
+    }
+
+    /// Enable interrupt for `event`, LEUART0 interrupt can wake the core from EM2.
+    pub fn listen(&mut self, event: Event) {
+        match event {
+            Event::RxDataValid => self.leuart.ien.modify(|_, w| w.rxdatav().set_bit()),
+            Event::StartFrame => self.leuart.ien.modify(|_, w| w.startf().set_bit()),
+            Event::SignalFrame => self.leuart.ien.modify(|_, w| w.sigf().set_bit()),
+        }
+    }
+
+    /// Disable interrupt for `event`.
+    pub fn unlisten(&mut self, event: Event) {
+        match event {
+            Event::RxDataValid => self.leuart.ien.modify(|_, w| w.rxdatav().clear_bit()),
+            Event::StartFrame => self.leuart.ien.modify(|_, w| w.startf().clear_bit()),
+            Event::SignalFrame => self.leuart.ien.modify(|_, w| w.sigf().clear_bit()),
+        }
+    }
+
+    /// Check whether `event` is pending.
+    pub fn is_pending(&self, event: Event) -> bool {
+        let flags = self.leuart.if_.read();
+        match event {
+            Event::RxDataValid => flags.rxdatav().bit_is_set(),
+            Event::StartFrame => flags.startf().bit_is_set(),
+            Event::SignalFrame => flags.sigf().bit_is_set(),
+        }
+    }
+
+    /// Clear pending `event`, `Event::RxDataValid` is cleared by reading the data.
+    pub fn clear(&mut self, event: Event) {
+        match event {
+            Event::RxDataValid => {}
+            Event::StartFrame => self.leuart.ifc.write(|w| w.startf().set_bit()),
+            Event::SignalFrame => self.leuart.ifc.write(|w| w.sigf().set_bit()),
+        }
+    }
+}
+
+impl<PINS> serial::Read<u8> for Serial<PINS> {
+    type Error = Error;
+
+    fn read(&mut self) -> nb::Result<u8, Error> {
+        let flags = self.leuart.if_.read();
+
+        let err = if flags.rxof().bit_is_set() {
+            Some(Error::Overrun)
+        } else if flags.perr().bit_is_set() {
+            Some(Error::Parity)
+        } else if flags.ferr().bit_is_set() {
+            Some(Error::Framing)
+        } else {
+            None
+        };
+
+        if let Some(err) = err {
+            self.leuart.ifc.write(|w| {
+                w.rxof().set_bit()
+                 .perr().set_bit()
+                 .ferr().set_bit()
+            });
+            return Err(nb::Error::Other(err));
+        }
+
+        if self.leuart.status.read().rxdatav().bit_is_clear() {
+            return Err(nb::Error::WouldBlock);
+        }
+
+        Ok(self.leuart.rxdata.read().rxdata().bits())
+    }
+}
+
+impl<PINS> serial::Write<u8> for Serial<PINS> {
+    type Error = Error;
+
+    fn write(&mut self, word: u8) -> nb::Result<(), Error> {
+        if self.leuart.status.read().txbl().bit_is_clear() {
+            return Err(nb::Error::WouldBlock);
+        }
+
+        self.sync();
+        self.leuart.txdata.write(|w| unsafe { w.txdata().bits(word) });
+
+        Ok(())
+    }
+
+    fn flush(&mut self) -> nb::Result<(), Error> {
+        if self.leuart.status.read().txc().bit_is_clear() {
+            return Err(nb::Error::WouldBlock);
+        }
+
+        Ok(())
+    }
+}
+
+impl<PINS> fmt::Write for Serial<PINS> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        use embedded_hal::serial::Write;
+
+        for byte in s.bytes() {
+            nb::block!(self.write(byte)).map_err(|_| fmt::Error)?;
+        }
+
+        Ok(())
+    }
+}
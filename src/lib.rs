@@ -1,20 +1,33 @@
 #![no_std]
 
 pub use efm32;
-pub use efm32_hal::{systick, systick::SystickExt, watchdog::WatchdogExt};
 
 #[cfg(feature = "rt")]
 pub use crate::efm32::interrupt;
 
+pub mod layout;
+pub mod clocks;
 pub mod toboot;
 
 pub mod led;
 pub mod uart;
+pub mod leuart;
+pub mod rtc;
+pub mod timer;
 pub mod usb;
+pub mod flash;
+pub mod crc;
+pub mod settings;
+pub mod update;
+pub mod watchdog;
 pub mod efm32hg;
 pub mod tomu;
 pub use tomu::Tomu;
 
+
+#[cfg(feature = "panic-led")]
+mod panic_led;
+
 #[cfg(feature = "toboot-custom-config")]
 pub use tomu_macros::toboot_config;
 
@@ -23,12 +36,8 @@ pub mod prelude {
     pub use embedded_hal::watchdog::Watchdog;
     pub use embedded_hal::watchdog::WatchdogDisable;
 
-    pub use efm32_hal::systick;
-
     pub use efm32_hal::cmu::CMUExt;
     pub use efm32_hal::gpio::GPIOExt;
-    pub use efm32_hal::systick::SystickExt;
-    pub use efm32_hal::watchdog::WatchdogExt;
 
     pub use crate::led;
     pub use crate::led::LedTrait;
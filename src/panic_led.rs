@@ -0,0 +1,159 @@
+//! Panic handler blinking the panic location on the red led
+//!
+//! Enabled with `panic-led` feature, in place of `panic-halt` & co.
+//! Interrupts are disabled and both led pins are taken over, whatever
+//! owned them before (GPIO, or a timer through `PwmLED`). The green led is
+//! turned off and the red one repeats:
+//!
+//! - a burst of fast flickers, telling a panic apart from any other pattern,
+//! - each decimal digit of the panic line number as that many blinks
+//!   (ten for 0), separated by a short pause,
+//! - a long pause.
+//!
+//! E.g. a panic at line 205 flickers, then blinks 2, 10 and 5 times.
+//!
+//! A running watchdog isn't touched, and still resets the device.
+//!
+//! With `panic-led-reboot` feature, the device resets into toboot after
+//! about `REBOOT_AFTER_SECS`, so new firmware can be uploaded right away.
+use core::panic::PanicInfo;
+
+/// Green led is PA0, red led is PB7, both active low.
+const GREEN_PIN: u32 = 0;
+const RED_PIN: u32 = 7;
+
+const FLICKERS: u32 = 8;
+const FLICKER_MS: u32 = 40;
+const BLINK_ON_MS: u32 = 250;
+const BLINK_OFF_MS: u32 = 250;
+const DIGIT_PAUSE_MS: u32 = 1000;
+const PATTERN_PAUSE_MS: u32 = 3000;
+
+#[cfg(feature = "panic-led-reboot")]
+const REBOOT_AFTER_SECS: u32 = 30;
+
+/// Busy wait based on the HFCLK found in CMU, clocks frozen by the
+/// application are out of reach here.
+struct Spin {
+    cycles_per_ms: u32,
+    #[cfg(feature = "panic-led-reboot")]
+    elapsed_ms: u32,
+}
+
+impl Spin {
+    fn new() -> Self {
+        let cmu = unsafe { &*efm32::CMU::ptr() };
+        let status = cmu.status.read();
+
+        let hz = if status.hfrcosel().bit_is_set() {
+            match (cmu.hfrcoctrl.read().bits() >> 8) & 0x7 {
+                0 => 1_200_000,
+                1 => 6_600_000,
+                2 => 11_000_000,
+                3 => 14_000_000,
+                _ => 21_000_000,
+            }
+        } else {
+            // USHFRCO divided by 2.
+            24_000_000
+        };
+        let divider = 1 << (cmu.hfcoreclkdiv.read().bits() & 0xf);
+
+        Spin {
+            cycles_per_ms: hz / divider / 1000,
+            #[cfg(feature = "panic-led-reboot")]
+            elapsed_ms: 0,
+        }
+    }
+
+    fn ms(&mut self, ms: u32) {
+        cortex_m::asm::delay(self.cycles_per_ms * ms);
+
+        #[cfg(feature = "panic-led-reboot")]
+        {
+            self.elapsed_ms = self.elapsed_ms.saturating_add(ms);
+        }
+    }
+}
+
+fn red(on: bool) {
+    let gpio = unsafe { &*efm32::GPIO::ptr() };
+    if on {
+        gpio.pb_doutclr.write(|w| unsafe { w.bits(1 << RED_PIN) });
+    } else {
+        gpio.pb_doutset.write(|w| unsafe { w.bits(1 << RED_PIN) });
+    }
+}
+
+fn blink(spin: &mut Spin, on_ms: u32, off_ms: u32) {
+    red(true);
+    spin.ms(on_ms);
+    red(false);
+    spin.ms(off_ms);
+}
+
+/// Take over the led pins, both off.
+fn steal_leds() {
+    let cmu = unsafe { &*efm32::CMU::ptr() };
+    let gpio = unsafe { &*efm32::GPIO::ptr() };
+    let timer0 = unsafe { &*efm32::TIMER0::ptr() };
+    let timer1 = unsafe { &*efm32::TIMER1::ptr() };
+
+    cmu.hfperclken0.modify(|_, w| w.gpio().set_bit());
+
+    // Drop PWM routes, if any, GPIO drives the pins from now on.
+    if cmu.hfperclken0.read().timer0().bit_is_set() {
+        timer0.route.reset();
+    }
+    if cmu.hfperclken0.read().timer1().bit_is_set() {
+        timer1.route.reset();
+    }
+
+    gpio.pa_doutset.write(|w| unsafe { w.bits(1 << GREEN_PIN) });
+    gpio.pb_doutset.write(|w| unsafe { w.bits(1 << RED_PIN) });
+    gpio.pa_model.modify(|_, w| w.mode0().pushpull());
+    gpio.pb_model.modify(|_, w| w.mode7().pushpull());
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    cortex_m::interrupt::disable();
+
+    steal_leds();
+
+    let line = info.location().map_or(0, |location| location.line());
+    let mut spin = Spin::new();
+
+    loop {
+        for _ in 0..FLICKERS {
+            blink(&mut spin, FLICKER_MS, FLICKER_MS);
+        }
+        spin.ms(DIGIT_PAUSE_MS);
+
+        // Most significant digit first.
+        let mut divisor = 1;
+        while line / divisor >= 10 {
+            divisor *= 10;
+        }
+        while divisor > 0 {
+            let digit = line / divisor % 10;
+            let blinks = if digit == 0 { 10 } else { digit };
+            for _ in 0..blinks {
+                blink(&mut spin, BLINK_ON_MS, BLINK_OFF_MS);
+            }
+            spin.ms(DIGIT_PAUSE_MS);
+            divisor /= 10;
+        }
+
+        spin.ms(PATTERN_PAUSE_MS);
+
+        #[cfg(feature = "panic-led-reboot")]
+        if spin.elapsed_ms >= REBOOT_AFTER_SECS * 1000 {
+            if crate::layout::BOOTLOADER {
+                crate::toboot::reboot_to_bootloader();
+            } else {
+                cortex_m::peripheral::SCB::sys_reset();
+            }
+        }
+    }
+}
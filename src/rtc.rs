@@ -0,0 +1,276 @@
+//! Real time counter (RTC) support for tomu
+//!
+//! RTC is a 24-bit counter clocked from LFA, it keeps running in EM2 and
+//! can wake the core up. At 32.768 kHz it wraps every 512 seconds, `Rtc`
+//! extends it to 64 bits by counting overflows, either from the RTC
+//! interrupt handler or whenever the time is read, so one of them has to
+//! happen at least once per wrap.
+//!
+//! On top of the tick counter it provides two alarms, an embedded-hal
+//! `CountDown`, and a wall clock in seconds since the epoch, which is
+//! unknown until set (e.g. from the host over USB).
+//!
+//! ``` no_run
+//! # use tomu::clocks::{LfConfig, LfOscillator};
+//! # use tomu::rtc::{Alarm, Rtc};
+//! # let p = tomu::efm32hg::Peripherals::take().unwrap();
+//! let mut tomu = tomu::Tomu::builder()
+//!     .lf_clocks(LfConfig::new().lfa(LfOscillator::Lfrco))
+//!     .build(p.CMU, p.WDOG, p.GPIO, p.SYST);
+//!
+//! let mut rtc = Rtc::new(p.RTC, tomu.lf_clocks.rtc.take().unwrap());
+//!
+//! let in_one_second = rtc.ticks() + u64::from(rtc.hz());
+//! rtc.set_alarm(Alarm::Alarm0, in_one_second);
+//!
+//! while !rtc.take_alarm(Alarm::Alarm0) {
+//!     cortex_m::asm::wfi();
+//! }
+//! ```
+use core::time::Duration;
+
+use efm32::RTC;
+use embedded_hal::timer::{CountDown, Periodic};
+use void::Void;
+
+use crate::clocks::RtcClock;
+
+/// Counter is 24 bits wide.
+const COUNTER_BITS: u32 = 24;
+const COUNTER_MASK: u32 = (1 << COUNTER_BITS) - 1;
+
+/// RTC compare alarm
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Alarm {
+    /// Compare channel 0
+    Alarm0,
+    /// Compare channel 1
+    Alarm1,
+}
+
+impl Alarm {
+    fn index(self) -> usize {
+        match self {
+            Alarm::Alarm0 => 0,
+            Alarm::Alarm1 => 1,
+        }
+    }
+}
+
+/// RTC driver
+pub struct Rtc {
+    rtc: RTC,
+    clock: RtcClock,
+    overflows: u64,
+    alarms: [Option<u64>; 2],
+    fired: [bool; 2],
+    countdown: Option<(u64, u64)>,
+    epoch: Option<u64>,
+}
+
+impl Rtc {
+    /// Reset and start RTC, counting at `clock` rate.
+    ///
+    /// Overflow interrupt is enabled, unmask `RTC` in NVIC and call
+    /// `on_interrupt` from its handler to keep the counter extended in sleep.
+    pub fn new(rtc: RTC, clock: RtcClock) -> Self {
+        rtc.freeze.reset();
+        rtc.ctrl.reset();
+        rtc.ien.reset();
+        rtc.ifc.write(|w| w.comp0().set_bit().comp1().set_bit().of().set_bit());
+
+        let mut rtc = Rtc {
+            rtc,
+            clock,
+            overflows: 0,
+            alarms: [None; 2],
+            fired: [false; 2],
+            countdown: None,
+            epoch: None,
+        };
+
+        rtc.sync();
+        rtc.rtc.ien.write(|w| w.of().set_bit());
+        rtc.rtc.ctrl.write(|w| w.en().set_bit());
+        rtc.sync();
+
+        rtc
+    }
+
+    /// Stop RTC and release the peripheral and its clock.
+    pub fn free(self) -> (RTC, RtcClock) {
+        self.rtc.ien.reset();
+        self.rtc.ctrl.reset();
+        self.sync();
+
+        (self.rtc, self.clock)
+    }
+
+    /// Wait until previous writes have been synchronized into the low frequency domain.
+    fn sync(&self) {
+        while self.rtc.syncbusy.read().bits() != 0 {}
+    }
+
+    /// Tick rate in Hz.
+    pub fn hz(&self) -> u32 {
+        self.clock.hz()
+    }
+
+    /// Raw 24-bit counter value.
+    pub fn counter(&self) -> u32 {
+        self.rtc.cnt.read().bits() & COUNTER_MASK
+    }
+
+    /// Count a pending overflow, if any.
+    fn update_overflow(&mut self) {
+        if self.rtc.if_.read().of().bit_is_set() {
+            self.rtc.ifc.write(|w| w.of().set_bit());
+            self.overflows += 1;
+        }
+    }
+
+    /// Ticks since `new`, extended to 64 bits.
+    pub fn ticks(&mut self) -> u64 {
+        loop {
+            self.update_overflow();
+            let counter = self.counter();
+
+            // Wrapped between the flag check and reading the counter,
+            // count it before using the value.
+            if self.rtc.if_.read().of().bit_is_clear() {
+                return (self.overflows << COUNTER_BITS) | u64::from(counter);
+            }
+        }
+    }
+
+    /// Handle RTC interrupt: count overflows and latch alarms that went off.
+    pub fn on_interrupt(&mut self) {
+        self.update_overflow();
+
+        let flags = self.rtc.if_.read();
+        let pending = [flags.comp0().bit_is_set(), flags.comp1().bit_is_set()];
+        self.rtc.ifc.write(|w| w.comp0().set_bit().comp1().set_bit());
+
+        let now = self.ticks();
+        for alarm in [Alarm::Alarm0, Alarm::Alarm1] {
+            if pending[alarm.index()] {
+                self.check_alarm(alarm, now);
+            }
+        }
+    }
+
+    /// Set `alarm` to go off at `at` ticks, replacing the previous one.
+    ///
+    /// Its interrupt is enabled so it can wake the core up. Alarms further
+    /// than a counter wrap away also interrupt on the way, `take_alarm`
+    /// only reports them once `at` is reached.
+    pub fn set_alarm(&mut self, alarm: Alarm, at: u64) {
+        let compare = at as u32 & COUNTER_MASK;
+
+        self.alarms[alarm.index()] = Some(at);
+        self.fired[alarm.index()] = false;
+
+        self.sync();
+        match alarm {
+            Alarm::Alarm0 => {
+                self.rtc.comp0.write(|w| unsafe { w.comp0().bits(compare) });
+                self.rtc.ifc.write(|w| w.comp0().set_bit());
+                self.rtc.ien.modify(|_, w| w.comp0().set_bit());
+            }
+            Alarm::Alarm1 => {
+                self.rtc.comp1.write(|w| unsafe { w.comp1().bits(compare) });
+                self.rtc.ifc.write(|w| w.comp1().set_bit());
+                self.rtc.ien.modify(|_, w| w.comp1().set_bit());
+            }
+        }
+
+        // Already in the past, or too close to be caught by the comparator.
+        let now = self.ticks();
+        self.check_alarm(alarm, now);
+    }
+
+    /// Disarm `alarm`.
+    pub fn cancel_alarm(&mut self, alarm: Alarm) {
+        self.alarms[alarm.index()] = None;
+        self.fired[alarm.index()] = false;
+        self.disable_alarm(alarm);
+    }
+
+    /// Return whether `alarm` went off, and clear it.
+    pub fn take_alarm(&mut self, alarm: Alarm) -> bool {
+        let now = self.ticks();
+        self.check_alarm(alarm, now);
+
+        core::mem::replace(&mut self.fired[alarm.index()], false)
+    }
+
+    fn check_alarm(&mut self, alarm: Alarm, now: u64) {
+        if let Some(at) = self.alarms[alarm.index()] {
+            if now >= at {
+                self.alarms[alarm.index()] = None;
+                self.fired[alarm.index()] = true;
+                self.disable_alarm(alarm);
+            }
+        }
+    }
+
+    fn disable_alarm(&mut self, alarm: Alarm) {
+        match alarm {
+            Alarm::Alarm0 => self.rtc.ien.modify(|_, w| w.comp0().clear_bit()),
+            Alarm::Alarm1 => self.rtc.ien.modify(|_, w| w.comp1().clear_bit()),
+        }
+    }
+
+    /// Convert `duration` to ticks, rounding up and saturating at
+    /// `u64::MAX`, which is never reached.
+    pub fn duration_to_ticks(&self, duration: Duration) -> u64 {
+        let hz = u64::from(self.hz());
+        // LFA runs at 32768 Hz at most, so this one fits easily.
+        let subsec = (u64::from(duration.subsec_nanos()) * hz).div_ceil(1_000_000_000);
+
+        duration.as_secs().saturating_mul(hz).saturating_add(subsec)
+    }
+
+    /// Set the wall clock to `seconds` since the epoch.
+    pub fn set_time(&mut self, seconds: u64) {
+        let hz = u64::from(self.hz());
+        let now = self.ticks();
+
+        // Epoch in ticks relative to the counter.
+        self.epoch = Some((seconds * hz).wrapping_sub(now));
+    }
+
+    /// Seconds since the epoch, `None` until `set_time` has been called.
+    pub fn time(&mut self) -> Option<u64> {
+        let hz = u64::from(self.hz());
+        let now = self.ticks();
+
+        self.epoch.map(|epoch| epoch.wrapping_add(now) / hz)
+    }
+}
+
+impl CountDown for Rtc {
+    type Time = Duration;
+
+    /// Start counting down `count`, it's restarted every time it expires.
+    fn start<T: Into<Duration>>(&mut self, count: T) {
+        let period = self.duration_to_ticks(count.into()).max(1);
+        let now = self.ticks();
+
+        self.countdown = Some((now.saturating_add(period), period));
+    }
+
+    fn wait(&mut self) -> nb::Result<(), Void> {
+        let now = self.ticks();
+
+        match &mut self.countdown {
+            Some((deadline, period)) if now >= *deadline => {
+                *deadline = deadline.saturating_add(*period);
+                Ok(())
+            }
+            _ => Err(nb::Error::WouldBlock),
+        }
+    }
+}
+
+impl Periodic for Rtc {}
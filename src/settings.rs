@@ -0,0 +1,423 @@
+//! Persistent key/value settings
+//!
+//! A small log-structured store on top of any `embedded-storage` `NorFlash`,
+//! usually `flash::Flash` over the `persistent-data` region. It takes the
+//! first two 1KiB pages of the flash and uses them alternately:
+//!
+//! - Every `set` or `remove` appends a CRC-checked record to the active page,
+//!   the last valid record for a key wins.
+//! - When the active page is full, live records are copied to the other page,
+//!   which only becomes active once its header is written. Power loss at any
+//!   point leaves either the old or the new page intact.
+//! - Records interrupted by power loss fail their CRC and are ignored.
+//!
+//! ``` no_run
+//! # use tomu::{flash::Flash, settings::Settings};
+//! # let p = tomu::efm32hg::Peripherals::take().unwrap();
+//! const BRIGHTNESS: u16 = 1;
+//!
+//! let mut settings = Settings::new(Flash::new(p.MSC)).unwrap();
+//! settings.set(BRIGHTNESS, &[128]).unwrap();
+//!
+//! let mut buf = [0u8; 1];
+//! if let Some(len) = settings.get(BRIGHTNESS, &mut buf).unwrap() {
+//!     // use buf[..len]
+//! }
+//! ```
+use embedded_storage::nor_flash::NorFlash;
+
+use crate::crc::crc32;
+use crate::layout::PAGE_SIZE;
+
+/// Marks a page as fully written, "STG1".
+const PAGE_MAGIC: u32 = 0x3147_5453;
+
+/// Page header: magic, then sequence number.
+const PAGE_HEADER_SIZE: u32 = 8;
+
+/// Record header (key and length) and trailing CRC.
+const RECORD_OVERHEAD: u32 = 8;
+
+/// Set in record length field for removed keys.
+const TOMBSTONE: u16 = 0x8000;
+
+const ERASED: u32 = 0xffff_ffff;
+
+/// Settings store error
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error<E> {
+    /// Underlying flash failed
+    Flash(E),
+    /// Flash is smaller than two pages
+    TooSmall,
+    /// Value is longer than `Settings::MAX_VALUE_LEN`
+    ValueTooLarge,
+    /// Live settings don't fit in a page, even after compaction
+    Full,
+    /// Value doesn't fit in the buffer, holds the value length
+    BufferTooSmall(usize),
+}
+
+/// Record found while scanning a page
+#[derive(Clone, Copy)]
+struct Record {
+    /// Offset within the page
+    pos: u32,
+    key: u16,
+    len: u16,
+    removed: bool,
+    valid: bool,
+}
+
+impl Record {
+    fn size(&self) -> u32 {
+        record_size(self.len.into())
+    }
+}
+
+fn record_size(len: u32) -> u32 {
+    RECORD_OVERHEAD + ((len + 3) & !3)
+}
+
+/// Key/value store over two flash pages
+pub struct Settings<F> {
+    flash: F,
+    /// Offset of the active page
+    active: u32,
+    /// Sequence number of the active page, increments on every compaction
+    seq: u32,
+    /// Offset within the active page where the next record goes
+    free: u32,
+}
+
+impl<F: NorFlash> Settings<F> {
+    /// Longest value a single key can hold.
+    pub const MAX_VALUE_LEN: usize = (PAGE_SIZE - PAGE_HEADER_SIZE - RECORD_OVERHEAD) as usize;
+
+    /// Mount the store, formatting it if neither page holds a valid header.
+    pub fn new(flash: F) -> Result<Self, Error<F::Error>> {
+        assert!(
+            (PAGE_SIZE as usize).is_multiple_of(F::ERASE_SIZE)
+                && 4usize.is_multiple_of(F::WRITE_SIZE)
+        );
+
+        if flash.capacity() < 2 * PAGE_SIZE as usize {
+            return Err(Error::TooSmall);
+        }
+
+        let mut settings = Settings {
+            flash,
+            active: 0,
+            seq: 0,
+            free: PAGE_HEADER_SIZE,
+        };
+
+        let pages = [settings.page_seq(0)?, settings.page_seq(PAGE_SIZE)?];
+        let (active, seq) = match pages {
+            [Some(a), Some(b)] if (b.wrapping_sub(a) as i32) > 0 => (PAGE_SIZE, b),
+            [Some(a), _] => (0, a),
+            [None, Some(b)] => (PAGE_SIZE, b),
+            [None, None] => {
+                settings.format(0, 0)?;
+                (0, 0)
+            }
+        };
+
+        settings.active = active;
+        settings.seq = seq;
+        settings.free = settings.scan(active, |_| {})?;
+
+        Ok(settings)
+    }
+
+    /// Release the underlying flash.
+    pub fn free(self) -> F {
+        self.flash
+    }
+
+    /// Read value of `key` into `buf`, returning its length,
+    /// or `None` if it's not set.
+    pub fn get(&mut self, key: u16, buf: &mut [u8]) -> Result<Option<usize>, Error<F::Error>> {
+        let record = match self.find(self.active, key)? {
+            Some(record) if !record.removed => record,
+            _ => return Ok(None),
+        };
+
+        let len = usize::from(record.len);
+        if buf.len() < len {
+            return Err(Error::BufferTooSmall(len));
+        }
+
+        self.read(self.active + record.pos + 4, &mut buf[..len])?;
+
+        Ok(Some(len))
+    }
+
+    /// Set `key` to `value`, compacting the store if the active page is full.
+    pub fn set(&mut self, key: u16, value: &[u8]) -> Result<(), Error<F::Error>> {
+        if value.len() > Self::MAX_VALUE_LEN {
+            return Err(Error::ValueTooLarge);
+        }
+
+        // Don't wear the flash out rewriting the same value.
+        if let Some(record) = self.find(self.active, key)? {
+            if !record.removed && usize::from(record.len) == value.len() {
+                let mut current = [0u8; 32];
+                let mut same = true;
+                let mut offset = 0;
+                while same && offset < value.len() {
+                    let chunk = (value.len() - offset).min(current.len());
+                    self.read(
+                        self.active + record.pos + 4 + offset as u32,
+                        &mut current[..chunk],
+                    )?;
+                    same = current[..chunk] == value[offset..offset + chunk];
+                    offset += chunk;
+                }
+
+                if same {
+                    return Ok(());
+                }
+            }
+        }
+
+        self.append(key, value.len() as u16, value)
+    }
+
+    /// Remove `key`, does nothing if it's not set.
+    pub fn remove(&mut self, key: u16) -> Result<(), Error<F::Error>> {
+        match self.find(self.active, key)? {
+            Some(record) if !record.removed => self.append(key, TOMBSTONE, &[]),
+            _ => Ok(()),
+        }
+    }
+
+    fn append(&mut self, key: u16, len: u16, value: &[u8]) -> Result<(), Error<F::Error>> {
+        let size = record_size(value.len() as u32);
+
+        if self.free + size > PAGE_SIZE {
+            return self.compact(key, len, value);
+        }
+
+        // Skip over the record even if writing fails halfway,
+        // it won't pass CRC check and mustn't be written over.
+        let pos = self.free;
+        self.free += size;
+
+        self.write_record(self.active + pos, key, len, value)
+    }
+
+    /// Copy live records, except `key`, into the other page followed by the
+    /// new record for `key`, then switch over to it.
+    fn compact(&mut self, key: u16, len: u16, value: &[u8]) -> Result<(), Error<F::Error>> {
+        let from = self.active;
+        let to = PAGE_SIZE - self.active;
+
+        self.flash.erase(to, to + PAGE_SIZE).map_err(Error::Flash)?;
+
+        let mut free = PAGE_HEADER_SIZE;
+        let mut record = self.next_record(from, PAGE_HEADER_SIZE)?;
+        while let Some(current) = record {
+            record = self.next_record(from, current.pos + current.size())?;
+
+            if !current.valid || current.removed || current.key == key {
+                continue;
+            }
+
+            // Only the last record for a key is live.
+            if self.find_after(from, current)?.is_some() {
+                continue;
+            }
+
+            if free + current.size() > PAGE_SIZE {
+                return Err(Error::Full);
+            }
+
+            let mut buf = [0u8; 32];
+            let mut offset = 0;
+            while offset < current.size() {
+                let chunk = (current.size() - offset).min(buf.len() as u32);
+                self.read(from + current.pos + offset, &mut buf[..chunk as usize])?;
+                self.write(to + free + offset, &buf[..chunk as usize])?;
+                offset += chunk;
+            }
+
+            free += current.size();
+        }
+
+        if len & TOMBSTONE == 0 {
+            let size = record_size(value.len() as u32);
+            if free + size > PAGE_SIZE {
+                return Err(Error::Full);
+            }
+
+            self.write_record(to + free, key, len, value)?;
+            free += size;
+        }
+
+        let seq = self.seq.wrapping_add(1);
+        self.write_header(to, seq)?;
+
+        self.active = to;
+        self.seq = seq;
+        self.free = free;
+
+        Ok(())
+    }
+
+    fn format(&mut self, page: u32, seq: u32) -> Result<(), Error<F::Error>> {
+        self.flash
+            .erase(page, page + PAGE_SIZE)
+            .map_err(Error::Flash)?;
+        self.write_header(page, seq)
+    }
+
+    /// Sequence number is written before the magic,
+    /// so a page only counts once its header is complete.
+    fn write_header(&mut self, page: u32, seq: u32) -> Result<(), Error<F::Error>> {
+        self.write(page + 4, &seq.to_le_bytes())?;
+        self.write(page, &PAGE_MAGIC.to_le_bytes())
+    }
+
+    fn page_seq(&mut self, page: u32) -> Result<Option<u32>, Error<F::Error>> {
+        if self.read_word(page)? != PAGE_MAGIC {
+            return Ok(None);
+        }
+
+        Ok(Some(self.read_word(page + 4)?))
+    }
+
+    /// Key and length go first, CRC last,
+    /// so a record is only valid once it's completely written.
+    fn write_record(
+        &mut self,
+        address: u32,
+        key: u16,
+        len: u16,
+        value: &[u8],
+    ) -> Result<(), Error<F::Error>> {
+        let mut header = [0u8; 4];
+        header[..2].copy_from_slice(&key.to_le_bytes());
+        header[2..].copy_from_slice(&len.to_le_bytes());
+        let crc = crc32(crc32(0, &header), value);
+
+        self.write(address, &header)?;
+
+        let aligned = value.len() & !3;
+        if aligned > 0 {
+            self.write(address + 4, &value[..aligned])?;
+        }
+
+        if aligned < value.len() {
+            let mut tail = [0xff; 4];
+            tail[..value.len() - aligned].copy_from_slice(&value[aligned..]);
+            self.write(address + 4 + aligned as u32, &tail)?;
+        }
+
+        self.write(
+            address + 4 + ((value.len() as u32 + 3) & !3),
+            &crc.to_le_bytes(),
+        )
+    }
+
+    /// Read record header at `pos` of `page`, `None` at the end of the log.
+    fn next_record(&mut self, page: u32, pos: u32) -> Result<Option<Record>, Error<F::Error>> {
+        if pos + RECORD_OVERHEAD > PAGE_SIZE {
+            return Ok(None);
+        }
+
+        let header = self.read_word(page + pos)?;
+        if header == ERASED {
+            return Ok(None);
+        }
+
+        let key = header as u16;
+        let len = (header >> 16) as u16;
+        let removed = len & TOMBSTONE != 0;
+        let len = len & !TOMBSTONE;
+
+        let record = Record {
+            pos,
+            key,
+            len,
+            removed,
+            valid: false,
+        };
+
+        // Garbage length, the rest of the page can't be trusted.
+        if pos + record.size() > PAGE_SIZE {
+            return Ok(None);
+        }
+
+        let mut crc = crc32(0, &header.to_le_bytes());
+        let mut buf = [0u8; 32];
+        let mut offset = 0;
+        while offset < u32::from(len) {
+            let chunk = (u32::from(len) - offset).min(buf.len() as u32) as usize;
+            self.read(page + pos + 4 + offset, &mut buf[..chunk])?;
+            crc = crc32(crc, &buf[..chunk]);
+            offset += chunk as u32;
+        }
+
+        let stored = self.read_word(page + pos + record.size() - 4)?;
+
+        Ok(Some(Record {
+            valid: stored == crc && stored != ERASED,
+            ..record
+        }))
+    }
+
+    /// Walk all records of `page`, returning where the log ends.
+    fn scan(&mut self, page: u32, mut f: impl FnMut(Record)) -> Result<u32, Error<F::Error>> {
+        let mut pos = PAGE_HEADER_SIZE;
+        let mut end = PAGE_HEADER_SIZE;
+
+        while let Some(record) = self.next_record(page, pos)? {
+            f(record);
+            pos += record.size();
+            end = pos;
+        }
+
+        // Anything after the log that isn't erased means a corrupted header,
+        // don't write there.
+        if pos + RECORD_OVERHEAD <= PAGE_SIZE && self.read_word(page + pos)? != ERASED {
+            end = PAGE_SIZE;
+        }
+
+        Ok(end)
+    }
+
+    /// Last valid record for `key` in `page`.
+    fn find(&mut self, page: u32, key: u16) -> Result<Option<Record>, Error<F::Error>> {
+        let mut found = None;
+        self.scan(page, |record| {
+            if record.valid && record.key == key {
+                found = Some(record);
+            }
+        })?;
+
+        Ok(found)
+    }
+
+    /// Valid record for the same key as `record`, written after it.
+    fn find_after(&mut self, page: u32, record: Record) -> Result<Option<Record>, Error<F::Error>> {
+        Ok(self
+            .find(page, record.key)?
+            .filter(|found| found.pos > record.pos))
+    }
+
+    fn read(&mut self, address: u32, buf: &mut [u8]) -> Result<(), Error<F::Error>> {
+        self.flash.read(address, buf).map_err(Error::Flash)
+    }
+
+    fn read_word(&mut self, address: u32) -> Result<u32, Error<F::Error>> {
+        let mut word = [0u8; 4];
+        self.read(address, &mut word)?;
+
+        Ok(u32::from_le_bytes(word))
+    }
+
+    fn write(&mut self, address: u32, bytes: &[u8]) -> Result<(), Error<F::Error>> {
+        self.flash.write(address, bytes).map_err(Error::Flash)
+    }
+}
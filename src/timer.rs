@@ -0,0 +1,267 @@
+//! Timer (TIMER0, TIMER1, TIMER2) support for tomu
+//!
+//! Each timer is a 16-bit up-counter clocked from HFPERCLK through a power
+//! of 2 prescaler (1 to 1024). `CountDown::start` picks the smallest
+//! prescaler that fits the requested frequency into the 16-bit TOP, so the
+//! resolution stays as high as possible.
+//!
+//! ``` no_run
+//! # use tomu::timer::{Event, Hertz, Timer};
+//! use embedded_hal::timer::CountDown;
+//!
+//! # let p = tomu::efm32hg::Peripherals::take().unwrap();
+//! # let tomu = tomu::Tomu::from_parts(p.CMU, p.WDOG, p.GPIO, p.SYST);
+//! let mut timer = Timer::timer1(p.TIMER1, &tomu.clocks);
+//! timer.start(Hertz(10));
+//!
+//! // Either poll it...
+//! nb::block!(timer.wait()).unwrap();
+//!
+//! // ...or interrupt on overflow, and `clear(Event::Overflow)` in the handler.
+//! timer.listen(Event::Overflow);
+//! ```
+use efm32::{TIMER0, TIMER1, TIMER2};
+use embedded_hal::timer::{CountDown, Periodic};
+use void::Void;
+
+use crate::clocks::Clocks;
+
+/// Largest prescaler setting, divide by 1024.
+const MAX_PRESCALER: u8 = 10;
+
+/// Frequency in Hz
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Hertz(pub u32);
+
+impl From<u32> for Hertz {
+    fn from(hz: u32) -> Self {
+        Hertz(hz)
+    }
+}
+
+/// Compare/capture channel
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Channel {
+    Cc0,
+    Cc1,
+    Cc2,
+}
+
+/// Timer interrupt events
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Event {
+    /// Counter wrapped from TOP to 0
+    Overflow,
+    /// Counter wrapped from 0 to TOP, when counting down
+    Underflow,
+    /// Counter matched the compare value of the channel
+    Compare(Channel),
+}
+
+/// Timer abstraction over TIMER0, TIMER1 or TIMER2
+pub struct Timer<TIM> {
+    tim: TIM,
+    hfperclk: u32,
+}
+
+/// Compute prescaler (log2 of the divisor) and TOP for `hz` from `hfperclk`.
+///
+/// Frequencies below `hfperclk / 1024 / 65535` are clamped to it. TOP stays
+/// below 0xffff, so an always on compare value, `top() + 1`, fits in CCVB.
+fn prescaler_and_top(hfperclk: u32, hz: u32) -> (u8, u16) {
+    let ticks = (hfperclk / hz.max(1)).max(1);
+
+    let mut prescaler = 0;
+    while prescaler < MAX_PRESCALER && ticks >> prescaler > 0xffff {
+        prescaler += 1;
+    }
+
+    let top = (ticks >> prescaler).clamp(1, 0xffff) - 1;
+    (prescaler, top as u16)
+}
+
+macro_rules! timers {
+    ($($TIMER:ident: ($timer:ident, $timerclken:ident),)+) => {
+        $(
+            impl Timer<$TIMER> {
+                /// Enable `TIMER` clock and take the timer, stopped.
+                ///
+                /// Frequencies are derived from HFPERCLK as frozen in `clocks`.
+                pub fn $timer(tim: $TIMER, clocks: &Clocks) -> Self {
+                    let cmu = unsafe { &*efm32::CMU::ptr() };
+
+                    critical_section::with(|_| {
+                        cmu.hfperclken0.modify(|_, w| w.$timerclken().set_bit());
+                    });
+
+                    tim.cmd.write(|w| w.stop().set_bit());
+                    tim.ctrl.reset();
+                    tim.ien.reset();
+                    tim.ifc.write(|w| unsafe { w.bits(0xffff_ffff) });
+                    // Out of reset TOP is 0xffff, keep it below like `start` does.
+                    tim.top.write(|w| unsafe { w.top().bits(0xfffe) });
+
+                    Timer { tim, hfperclk: clocks.hfperclk() }
+                }
+
+                /// Stop the timer and release the peripheral.
+                pub fn free(self) -> $TIMER {
+                    self.tim.cmd.write(|w| w.stop().set_bit());
+                    self.tim.ien.reset();
+
+                    self.tim
+                }
+
+                /// Stop counting, `CountDown::start` restarts it.
+                pub fn stop(&mut self) {
+                    self.tim.cmd.write(|w| w.stop().set_bit());
+                }
+
+                /// Current counter value.
+                pub fn counter(&self) -> u16 {
+                    self.tim.cnt.read().cnt().bits()
+                }
+
+                /// Counter value the timer wraps at.
+                pub fn top(&self) -> u16 {
+                    self.tim.top.read().top().bits()
+                }
+
+                /// Counter frequency in Hz, after prescaler.
+                pub fn tick_hz(&self) -> u32 {
+                    self.hfperclk >> self.tim.ctrl.read().presc().bits()
+                }
+
+                /// Raise `Event::Compare(channel)` when the counter matches `value`.
+                pub fn set_compare(&mut self, channel: Channel, value: u16) {
+                    match channel {
+                        Channel::Cc0 => {
+                            self.tim.cc0_ctrl.modify(|_, w| w.mode().outputcompare());
+                            self.tim.cc0_ccv.write(|w| unsafe { w.ccv().bits(value) });
+                        }
+                        Channel::Cc1 => {
+                            self.tim.cc1_ctrl.modify(|_, w| w.mode().outputcompare());
+                            self.tim.cc1_ccv.write(|w| unsafe { w.ccv().bits(value) });
+                        }
+                        Channel::Cc2 => {
+                            self.tim.cc2_ctrl.modify(|_, w| w.mode().outputcompare());
+                            self.tim.cc2_ccv.write(|w| unsafe { w.ccv().bits(value) });
+                        }
+                    }
+                }
+
+                /// Use `channel` for PWM: output is set on overflow and cleared on
+                /// compare match, or the other way around when `inverted`.
+                pub fn enable_pwm(&mut self, channel: Channel, inverted: bool) {
+                    match channel {
+                        Channel::Cc0 => self.tim.cc0_ctrl.modify(|_, w| w.mode().pwm().outinv().bit(inverted)),
+                        Channel::Cc1 => self.tim.cc1_ctrl.modify(|_, w| w.mode().pwm().outinv().bit(inverted)),
+                        Channel::Cc2 => self.tim.cc2_ctrl.modify(|_, w| w.mode().pwm().outinv().bit(inverted)),
+                    }
+                }
+
+                /// Set PWM compare value of `channel`, from 0 to `top() + 1` (always on).
+                /// TOP is kept below 0xffff, so the latter always fits.
+                ///
+                /// The value is buffered, and takes effect on the next overflow.
+                pub fn set_duty(&mut self, channel: Channel, duty: u16) {
+                    match channel {
+                        Channel::Cc0 => self.tim.cc0_ccvb.write(|w| unsafe { w.ccvb().bits(duty) }),
+                        Channel::Cc1 => self.tim.cc1_ccvb.write(|w| unsafe { w.ccvb().bits(duty) }),
+                        Channel::Cc2 => self.tim.cc2_ccvb.write(|w| unsafe { w.ccvb().bits(duty) }),
+                    }
+                }
+
+                /// Drive the pin at route `location` from `channel` output, or release it.
+                pub(crate) fn route(&mut self, channel: Channel, location: u8, enable: bool) {
+                    self.tim.route.modify(|_, w| unsafe {
+                        let w = w.location().bits(location);
+                        match channel {
+                            Channel::Cc0 => w.cc0pen().bit(enable),
+                            Channel::Cc1 => w.cc1pen().bit(enable),
+                            Channel::Cc2 => w.cc2pen().bit(enable),
+                        }
+                    });
+                }
+
+                /// Enable interrupt for `event`.
+                pub fn listen(&mut self, event: Event) {
+                    match event {
+                        Event::Overflow => self.tim.ien.modify(|_, w| w.of().set_bit()),
+                        Event::Underflow => self.tim.ien.modify(|_, w| w.uf().set_bit()),
+                        Event::Compare(Channel::Cc0) => self.tim.ien.modify(|_, w| w.cc0().set_bit()),
+                        Event::Compare(Channel::Cc1) => self.tim.ien.modify(|_, w| w.cc1().set_bit()),
+                        Event::Compare(Channel::Cc2) => self.tim.ien.modify(|_, w| w.cc2().set_bit()),
+                    }
+                }
+
+                /// Disable interrupt for `event`.
+                pub fn unlisten(&mut self, event: Event) {
+                    match event {
+                        Event::Overflow => self.tim.ien.modify(|_, w| w.of().clear_bit()),
+                        Event::Underflow => self.tim.ien.modify(|_, w| w.uf().clear_bit()),
+                        Event::Compare(Channel::Cc0) => self.tim.ien.modify(|_, w| w.cc0().clear_bit()),
+                        Event::Compare(Channel::Cc1) => self.tim.ien.modify(|_, w| w.cc1().clear_bit()),
+                        Event::Compare(Channel::Cc2) => self.tim.ien.modify(|_, w| w.cc2().clear_bit()),
+                    }
+                }
+
+                /// Check whether `event` is pending.
+                pub fn is_pending(&self, event: Event) -> bool {
+                    let flags = self.tim.if_.read();
+                    match event {
+                        Event::Overflow => flags.of().bit_is_set(),
+                        Event::Underflow => flags.uf().bit_is_set(),
+                        Event::Compare(Channel::Cc0) => flags.cc0().bit_is_set(),
+                        Event::Compare(Channel::Cc1) => flags.cc1().bit_is_set(),
+                        Event::Compare(Channel::Cc2) => flags.cc2().bit_is_set(),
+                    }
+                }
+
+                /// Clear pending `event`.
+                pub fn clear(&mut self, event: Event) {
+                    match event {
+                        Event::Overflow => self.tim.ifc.write(|w| w.of().set_bit()),
+                        Event::Underflow => self.tim.ifc.write(|w| w.uf().set_bit()),
+                        Event::Compare(Channel::Cc0) => self.tim.ifc.write(|w| w.cc0().set_bit()),
+                        Event::Compare(Channel::Cc1) => self.tim.ifc.write(|w| w.cc1().set_bit()),
+                        Event::Compare(Channel::Cc2) => self.tim.ifc.write(|w| w.cc2().set_bit()),
+                    }
+                }
+            }
+
+            impl CountDown for Timer<$TIMER> {
+                type Time = Hertz;
+
+                /// Start counting, overflowing at `count` frequency.
+                fn start<T: Into<Hertz>>(&mut self, count: T) {
+                    let (prescaler, top) = prescaler_and_top(self.hfperclk, count.into().0);
+
+                    self.tim.cmd.write(|w| w.stop().set_bit());
+                    self.tim.ctrl.modify(|_, w| unsafe { w.presc().bits(prescaler) });
+                    self.tim.top.write(|w| unsafe { w.top().bits(top) });
+                    self.tim.cnt.write(|w| unsafe { w.cnt().bits(0) });
+                    self.tim.ifc.write(|w| w.of().set_bit());
+                    self.tim.cmd.write(|w| w.start().set_bit());
+                }
+
+                fn wait(&mut self) -> nb::Result<(), Void> {
+                    if self.tim.if_.read().of().bit_is_clear() {
+                        return Err(nb::Error::WouldBlock);
+                    }
+
+                    self.tim.ifc.write(|w| w.of().set_bit());
+                    Ok(())
+                }
+            }
+
+            impl Periodic for Timer<$TIMER> {}
+        )+
+    }
+}
+
+timers! {
+    TIMER0: (timer0, timer0),
+    TIMER1: (timer1, timer1),
+    TIMER2: (timer2, timer2),
+}
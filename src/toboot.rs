@@ -1,12 +1,75 @@
-pub const TOBOOT_V2_MAGIC: u32 = 0x907070b2;
-pub const TOBOOT_LOCK_ENTRY_MAGIC: u32 = 0x18349420;
+//! Tomu Bootloader (toboot v2) support
+//!
+//! Toboot reads a config header right after the vector table: whether to
+//! autorun the application, lock bootloader entry, and which extra sectors
+//! to erase on update. A default header is emitted unless
+//! `toboot-custom-config` feature is enabled, then it comes from either the
+//! `toboot_config!` macro or a `TobootConfig` static built with the
+//! `const fn` builder:
+//!
+//! ``` ignore
+//! toboot_config! {
+//!     config: [autorun_enable, irq_enable],
+//!     erase: [0x8000..0xa000, sector(40)],
+//! }
+//! ```
+//!
+//! Locking bootloader entry fails to compile unless acknowledged with
+//! `i_understand_this_locks_the_bootloader: true`, and still warns then.
+//! Invalid configs are covered by compile-fail tests, run on host with
+//! `cargo +nightly test --test compiletest --target x86_64-unknown-linux-gnu`.
+//!
+//! At runtime, `current_config` reads back the header toboot has written,
+//! including the generation it increments on every upload, and
+//! `reboot_to_bootloader` resets into toboot without shorting the outer pins.
+//!
+//! Toboot API reference: <https://github.com/im-tomu/tomu-bootloader/blob/master/API.md>
+use crate::layout::{FLASH_SIZE, PAGE_SIZE, TOBOOT_SECTORS};
+
+// Header format is shared with host tools, `tomu-image` has the one codec.
+pub use tomu_image::{
+    TOBOOT_CONFIG_FLAG_AUTORUN, TOBOOT_CONFIG_FLAG_ENABLE_IRQ, TOBOOT_HASH_SEED,
+    TOBOOT_LOCK_ENTRY_MAGIC, TOBOOT_V2_MAGIC,
+};
+
+pub const TOBOOT_FORCE_ENTRY_MAGIC: u32 = 0x74624346;
+
+/// Toboot checks the first word of RAM on boot, if it contains
+/// `TOBOOT_FORCE_ENTRY_MAGIC` toboot will stay in the bootloader.
+const TOBOOT_BOOT_TOKEN: *mut u32 = 0x2000_0000 as *mut u32;
 
 /// Configuration for Tomu Bootloader (toboot)
 ///
 /// Application can be configured to work with Tomu Bootloader,
 /// and not all fields here should be set to work. Use `toboot_config!` macro
-/// below to config only certain fields
-#[repr(packed)]
+/// below to config only certain fields, or build it with the `const fn` builder
+/// methods when proc macro is not an option:
+///
+/// ``` no_run
+/// # use tomu::toboot::TobootConfig;
+/// #[used]
+/// #[no_mangle]
+/// static CONFIG: TobootConfig = TobootConfig::new()
+///     .autorun()
+///     .erase_range(0x8000..0xa000)
+///     .erase_sector(40);
+///
+/// tomu::toboot_erase_masks!(CONFIG);
+/// # fn main() {}
+/// ```
+///
+/// Either way, the default config has to be disabled
+/// by enabling `toboot-custom-config` feature.
+///
+/// The builder methods enforce the same rules `toboot_config!` does,
+/// invalid config will fail to compile when used in a `static`.
+/// Sectors taken by the application image are only known at link time,
+/// `toboot_erase_masks!` exports the masks for `memory.x` to check them,
+/// as `toboot_config!` does on its own.
+///
+/// To inspect the header toboot has actually written to flash, see `current_config`.
+#[derive(Clone, Copy)]
+#[repr(C, packed)]
 pub struct TobootConfig {
     /// This is the magic header for toboot config,
     /// for toboot v2 the value should be: 0x907070b2
@@ -17,7 +80,7 @@ pub struct TobootConfig {
     pub reserved_gen: u16,
 
     /// The start page of the program, there is no need to set this and should be left to
-    /// its default value, for application this will be set to 16 (16 * 1024 = 0x4000).
+    /// its default value, `layout::APP_START_PAGE` (16 * 1024 = 0x4000 unless changed).
     pub start: u8,
 
     /// Configuration bitmask, see `config_val` method below for possible values.
@@ -41,16 +104,251 @@ pub struct TobootConfig {
     pub reserved_hash: u32,
 }
 
-#[cfg(not(feature = "custom-toboot-config"))]
+impl TobootConfig {
+    /// Default toboot config, application starts at the page it's linked at
+    /// (`layout::APP_START_PAGE`), no flags set, and no extra sectors erased.
+    pub const fn new() -> Self {
+        TobootConfig {
+            magic: TOBOOT_V2_MAGIC,
+            reserved_gen: 0,
+            start: crate::layout::APP_START_PAGE as u8,
+            config: 0,
+            lock_entry: 0,
+            erase_mask_lo: 0,
+            erase_mask_hi: 0,
+            reserved_hash: 0,
+        }
+    }
+
+    /// Let the application handle interrupts while toboot is running.
+    pub const fn irq_enable(mut self) -> Self {
+        self.config |= TOBOOT_CONFIG_FLAG_ENABLE_IRQ;
+        self
+    }
+
+    /// Always run the application on boot, toboot can then only be entered
+    /// by shorting the outer pins (or `reboot_to_bootloader`).
+    pub const fn autorun(mut self) -> Self {
+        self.config |= TOBOOT_CONFIG_FLAG_AUTORUN;
+        self
+    }
+
+    /// Disable manual bootloader entry, the outer pins will no longer enter toboot.
+    pub const fn lock_entry_i_understand_this_locks_the_bootloader(mut self) -> Self {
+        self.lock_entry = TOBOOT_LOCK_ENTRY_MAGIC;
+        self
+    }
+
+    /// Erase flash `range` (in bytes) when updating program.
+    ///
+    /// Range has to be page-aligned (1KiB), and must not go beyond 64KiB flash
+    /// or touch toboot sectors (0-15).
+    pub const fn erase_range(self, range: core::ops::Range<u32>) -> Self {
+        if !range.start.is_multiple_of(PAGE_SIZE) || !range.end.is_multiple_of(PAGE_SIZE) {
+            panic!("flash range is not page-aligned, start and end address must be multiple of 0x400 (1KiB)");
+        }
+
+        if range.start >= range.end {
+            panic!("flash range is empty");
+        }
+
+        if range.end > FLASH_SIZE {
+            panic!("flash range exceeds flash size (0x10000)");
+        }
+
+        let mut result = self;
+        let mut sector = range.start / PAGE_SIZE;
+        while sector < range.end / PAGE_SIZE {
+            result = result.erase_sector(sector);
+            sector += 1;
+        }
+
+        result
+    }
+
+    /// Erase flash `sector` (1KiB each) when updating program.
+    ///
+    /// Sector must be within 64KiB flash, and not one of toboot sectors (0-15).
+    pub const fn erase_sector(mut self, sector: u32) -> Self {
+        if sector >= FLASH_SIZE / PAGE_SIZE {
+            panic!("sector is out of flash, valid sectors are 0-63");
+        }
+
+        if sector < TOBOOT_SECTORS {
+            panic!("sectors 0-15 (0x0..0x4000) are owned by toboot and cannot be erased");
+        }
+
+        if sector < 32 {
+            self.erase_mask_lo |= 1 << sector;
+        } else {
+            self.erase_mask_hi |= 1 << (sector - 32);
+        }
+
+        self
+    }
+
+    /// Set raw bitmask for sectors 0-31, bits for toboot sectors (0-15) must not be set.
+    pub const fn erase_mask_lo(mut self, mask: u32) -> Self {
+        if mask & ((1 << TOBOOT_SECTORS) - 1) != 0 {
+            panic!("erase_mask_lo sets bits for sectors 0-15, these are owned by toboot and cannot be erased");
+        }
+
+        self.erase_mask_lo = mask;
+        self
+    }
+
+    /// Set raw bitmask for sectors 32-63.
+    pub const fn erase_mask_hi(mut self, mask: u32) -> Self {
+        self.erase_mask_hi = mask;
+        self
+    }
+
+    /// Image generation, toboot increments this every time a new program is flashed.
+    pub fn generation(&self) -> u16 {
+        self.reserved_gen
+    }
+
+    /// Start page of the program.
+    pub fn start_page(&self) -> u8 {
+        self.start
+    }
+
+    /// Whether `TOBOOT_CONFIG_FLAG_ENABLE_IRQ` is set.
+    pub fn irq_enabled(&self) -> bool {
+        self.config & TOBOOT_CONFIG_FLAG_ENABLE_IRQ != 0
+    }
+
+    /// Whether `TOBOOT_CONFIG_FLAG_AUTORUN` is set.
+    pub fn autorun_enabled(&self) -> bool {
+        self.config & TOBOOT_CONFIG_FLAG_AUTORUN != 0
+    }
+
+    /// Whether manual bootloader entry is locked.
+    pub fn entry_locked(&self) -> bool {
+        self.lock_entry == TOBOOT_LOCK_ENTRY_MAGIC
+    }
+
+    /// Combined erase mask for sectors 0-63.
+    pub fn erase_mask(&self) -> u64 {
+        u64::from(self.erase_mask_hi) << 32 | u64::from(self.erase_mask_lo)
+    }
+
+    /// Header hash as computed by toboot.
+    pub fn hash(&self) -> u32 {
+        self.reserved_hash
+    }
+
+    /// Decode config header from its in-flash (little endian) representation.
+    pub fn from_bytes(bytes: &[u8; tomu_image::CONFIG_SIZE]) -> Self {
+        tomu_image::TobootConfig::from_bytes(bytes).into()
+    }
+
+    /// Encode config header into its in-flash (little endian) representation.
+    pub fn to_bytes(&self) -> [u8; tomu_image::CONFIG_SIZE] {
+        tomu_image::TobootConfig::from(*self).to_bytes()
+    }
+
+    /// Compute header hash the same way toboot does,
+    /// XXH32 of the whole header minus the `reserved_hash` field.
+    pub fn compute_hash(&self) -> u32 {
+        tomu_image::TobootConfig::from(*self).compute_hash()
+    }
+}
+
+impl Default for TobootConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl From<tomu_image::TobootConfig> for TobootConfig {
+    fn from(config: tomu_image::TobootConfig) -> Self {
+        TobootConfig {
+            magic: config.magic,
+            reserved_gen: config.reserved_gen,
+            start: config.start,
+            config: config.config,
+            lock_entry: config.lock_entry,
+            erase_mask_lo: config.erase_mask_lo,
+            erase_mask_hi: config.erase_mask_hi,
+            reserved_hash: config.reserved_hash,
+        }
+    }
+}
+
+impl From<TobootConfig> for tomu_image::TobootConfig {
+    fn from(config: TobootConfig) -> Self {
+        tomu_image::TobootConfig {
+            magic: config.magic,
+            reserved_gen: config.reserved_gen,
+            start: config.start,
+            config: config.config,
+            lock_entry: config.lock_entry,
+            erase_mask_lo: config.erase_mask_lo,
+            erase_mask_hi: config.erase_mask_hi,
+            reserved_hash: config.reserved_hash,
+        }
+    }
+}
+
+/// Export erase masks of a `TobootConfig` static built with the `const fn`
+/// builder, so the link fails if they overlap with the application image.
+/// Has to be invoked at module level, next to the static.
+///
+/// `toboot_config!` already does this, it must not be used along with it.
+#[macro_export]
+macro_rules! toboot_erase_masks {
+    ($config:expr) => {
+        ::core::arch::global_asm!(
+            ".globl __toboot_erase_mask_lo__",
+            ".set __toboot_erase_mask_lo__, {lo}",
+            ".globl __toboot_erase_mask_hi__",
+            ".set __toboot_erase_mask_hi__, {hi}",
+            lo = const $config.erase_mask_lo,
+            hi = const $config.erase_mask_hi,
+        );
+    };
+}
+
+/// Read toboot config header of the running image from flash.
+///
+/// Unlike reading `CONFIG` directly, which may be evaluated at compile time,
+/// this returns the header as it is in flash, including `reserved_gen` and
+/// `reserved_hash` that toboot rewrites when the image is flashed.
+pub fn current_config() -> TobootConfig {
+    extern "C" {
+        // Either the default config below or the one from `toboot_config!`,
+        // memory.x places it right after the vector table.
+        #[link_name = "CONFIG"]
+        static TOBOOT_HEADER: TobootConfig;
+    }
+
+    unsafe { core::ptr::read_volatile(core::ptr::addr_of!(TOBOOT_HEADER)) }
+}
+
+#[cfg(not(feature = "toboot-custom-config"))]
 #[used]
 #[no_mangle]
-pub static CONFIG: TobootConfig = TobootConfig {
-    magic: TOBOOT_V2_MAGIC,
-    reserved_gen: 0,
-    start: 16,
-    config: 0,
-    lock_entry: 0,
-    erase_mask_lo: 0,
-    erase_mask_hi: 0,
-    reserved_hash: 0,
-};
+pub static CONFIG: TobootConfig = TobootConfig::new();
+
+/// Reset the device into toboot, so new firmware can be uploaded
+/// without having to short the outer pins.
+///
+/// This works regardless of `lock_entry` setting.
+pub fn reboot_to_bootloader() -> ! {
+    reboot_with_token(TOBOOT_FORCE_ENTRY_MAGIC)
+}
+
+/// Reset the device and let toboot start the application as usual.
+pub fn reboot_to_application() -> ! {
+    reboot_with_token(0)
+}
+
+fn reboot_with_token(token: u32) -> ! {
+    cortex_m::interrupt::disable();
+
+    // Nothing in RAM matters past this point, the device is reset right after.
+    unsafe { core::ptr::write_volatile(TOBOOT_BOOT_TOKEN, token) };
+
+    cortex_m::peripheral::SCB::sys_reset()
+}
@@ -1,29 +1,105 @@
+use cortex_m::delay::Delay;
 use efm32_hal::{
-    watchdog::{Watchdog, WatchdogExt},
     cmu::CMUExt, gpio::GPIOExt,
     gpio::pins, gpio::common::{Disabled, Floating},
-    delay::{CountProvider, Delay},
-    systick::{SystickDelay, SystickExt},
 };
+use crate::clocks::{self, Clocks, HfrcoBand, LfClocks, LfConfig, Prescaler, WdogClock};
 use crate::efm32hg;
 use crate::led::LEDs;
+use crate::watchdog::Watchdog;
 
 pub struct Tomu {
     pub gpio: TomuFreeGPIO,
     pub leds: LEDs,
+    /// SysTick delay, running at `clocks.hfcoreclk()`
     pub delay: Delay,
+    /// Clocked from `lf_clocks.wdog` if configured, ULFRCO otherwise
     pub watchdog: Watchdog,
+    pub clocks: Clocks,
+    pub lf_clocks: LfClocks,
 }
 
 impl Tomu {
+    /// Configure the clock tree before taking the peripherals,
+    /// see `TomuBuilder`.
+    pub fn builder() -> TomuBuilder {
+        TomuBuilder {
+            clocks: clocks::Config::new(),
+            lf_clocks: LfConfig::new(),
+        }
+    }
+
+    /// Take the peripherals with the reset clock tree (HFRCO at 14 MHz).
     pub fn from_parts(cmu: efm32::CMU, wdog: efm32::WDOG, gpio: efm32::GPIO, syst: efm32::SYST) -> Self {
-        let clocks = cmu.constrain().freeze();
-        let systick_delay = SystickDelay::new(syst.constrain(), &clocks);
-        let gpio = gpio.constrain(clocks.gpio).split();
-        Self {
-            watchdog: wdog.constrain(),
+        Self::builder().build(cmu, wdog, gpio, syst)
+    }
+    pub fn from(efm32: efm32hg::Peripherals) -> Self {
+        Self::from_parts(efm32.CMU, efm32.WDOG, efm32.GPIO, efm32.SYST)
+    }
+}
+
+/// `Tomu` with a configured clock tree, e.g. for USB:
+///
+/// ``` no_run
+/// # let p = tomu::efm32hg::Peripherals::take().unwrap();
+/// let tomu = tomu::Tomu::builder()
+///     .ushfrco()
+///     .build(p.CMU, p.WDOG, p.GPIO, p.SYST);
+///
+/// let usb = tomu::usb::USB::new(p.USB, &tomu.clocks);
+/// ```
+pub struct TomuBuilder {
+    clocks: clocks::Config,
+    lf_clocks: LfConfig,
+}
+
+impl TomuBuilder {
+    /// Run HFCLK from HFRCO, tuned to `band`.
+    pub fn hfrco(mut self, band: HfrcoBand) -> Self {
+        self.clocks = self.clocks.hfrco(band);
+        self
+    }
+
+    /// Run HFCLK from USHFRCO divided by 2 (24 MHz), as required by USB.
+    pub fn ushfrco(mut self) -> Self {
+        self.clocks = self.clocks.ushfrco();
+        self
+    }
+
+    /// Divide HFCLK for the core, DMA and USB (HFCORECLK).
+    pub fn hfcore_prescaler(mut self, prescaler: Prescaler) -> Self {
+        self.clocks = self.clocks.hfcore_prescaler(prescaler);
+        self
+    }
+
+    /// Divide HFCLK for peripherals (HFPERCLK).
+    pub fn hfper_prescaler(mut self, prescaler: Prescaler) -> Self {
+        self.clocks = self.clocks.hfper_prescaler(prescaler);
+        self
+    }
+
+    /// Enable and route low frequency clocks, their tokens end up in `Tomu::lf_clocks`.
+    pub fn lf_clocks(mut self, config: LfConfig) -> Self {
+        self.lf_clocks = config;
+        self
+    }
+
+    /// Apply the clock configuration and take the peripherals.
+    pub fn build(self, cmu: efm32::CMU, wdog: efm32::WDOG, gpio: efm32::GPIO, syst: efm32::SYST) -> Tomu {
+        let clocks = self.clocks.freeze(&cmu);
+        let mut lf_clocks = self.lf_clocks.freeze(&cmu);
+        let wdog_clock = lf_clocks.wdog.take().unwrap_or_else(WdogClock::ulfrco);
+
+        // Only the GPIO clock gate is taken from the hal, its frequencies
+        // assume the reset clock tree, `clocks` has the configured ones.
+        let gpio = gpio.constrain(cmu.constrain().freeze().gpio).split();
+
+        Tomu {
+            clocks,
+            lf_clocks,
+            watchdog: Watchdog::new(wdog, wdog_clock),
             leds: LEDs::new(gpio.pa0.into(), gpio.pb7.into()),
-            delay: Delay::new(CountProvider::SysTick(systick_delay)),
+            delay: Delay::new(syst, clocks.hfcoreclk()),
             gpio: TomuFreeGPIO {
                 pb8: gpio.pb8,
                 pb11: gpio.pb11,
@@ -41,9 +117,6 @@ impl Tomu {
             },
         }
     }
-    pub fn from(efm32: efm32hg::Peripherals) -> Self {
-        Self::from_parts(efm32.CMU, efm32.WDOG, efm32.GPIO, efm32.SYST)
-    }
 }
 
 pub struct TomuFreeGPIO {
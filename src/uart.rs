@@ -1 +1,233 @@
+//! Serial (UART) support for tomu
+//!
+//! Both USART0 and USART1 can be used in asynchronous mode with the free
+//! tomu pins. Only pin pairs the efm32hg309 can actually route are accepted,
+//! the route location is derived from the pins passed in:
+//!
+//! | USART  | TX   | RX   | location |
+//! |--------|------|------|----------|
+//! | USART0 | PE13 | PE12 | 3        |
+//! | USART0 | PC0  | PC1  | 5        |
+//! | USART1 | PC0  | PC1  | 0        |
+//!
+//! LEUART0 routes to these pins, see `leuart`:
+//!
+//! | LEUART  | TX   | RX   | location |
+//! |---------|------|------|----------|
+//! | LEUART0 | PB13 | PB14 | 1        |
+//! | LEUART0 | PF0  | PF1  | 3        |
+//!
+//! Frame format is fixed to 8N1 with 16x oversampling.
+use core::fmt;
 
+use efm32::{LEUART0, USART0, USART1};
+use efm32_hal::gpio::{
+    common::{Disabled, Floating},
+    pins::{PB13, PB14, PC0, PC1, PE12, PE13, PF0, PF1},
+};
+use embedded_hal::serial;
+
+use crate::clocks::Clocks;
+
+/// Serial error
+#[derive(Debug)]
+pub enum Error {
+    /// Data was received while the receive buffer is full
+    Overrun,
+    /// Parity check failed
+    Parity,
+    /// Stop bit was not detected
+    Framing,
+}
+
+/// TX/RX pin pair which can be routed to `USART`.
+///
+/// This trait is sealed, it's implemented only for pin combinations
+/// that the efm32hg309 supports.
+pub trait Pins<USART>: private::Sealed {
+    /// Route location for this pin pair
+    const LOCATION: u8;
+
+    /// Configure TX as push-pull output (idling high) and RX as input.
+    #[doc(hidden)]
+    fn setup(&self);
+}
+
+mod private {
+    use super::*;
+
+    pub trait Sealed {}
+
+    impl Sealed for (PE13<Disabled<Floating>>, PE12<Disabled<Floating>>) {}
+    impl Sealed for (PC0<Disabled<Floating>>, PC1<Disabled<Floating>>) {}
+    impl Sealed for (PB13<Disabled<Floating>>, PB14<Disabled<Floating>>) {}
+    impl Sealed for (PF0<Disabled<Floating>>, PF1<Disabled<Floating>>) {}
+}
+
+macro_rules! pins {
+    ($($USART:ident: ($TX:ident, $txmode:ident, $txfield:ident, $txdoutset:ident, $txpin:expr,
+                      $RX:ident, $rxmode:ident, $rxfield:ident) => $loc:expr,)+) => {
+        $(
+            impl Pins<$USART> for ($TX<Disabled<Floating>>, $RX<Disabled<Floating>>) {
+                const LOCATION: u8 = $loc;
+
+                fn setup(&self) {
+                    let gpio = unsafe { &*efm32::GPIO::ptr() };
+
+                    critical_section::with(|_| {
+                        // Set TX line high before enabling output so no false
+                        // start bit is sent.
+                        gpio.$txdoutset.write(|w| unsafe { w.bits(1 << $txpin) });
+                        gpio.$txmode.modify(|_, w| w.$txfield().pushpull());
+                        gpio.$rxmode.modify(|_, w| w.$rxfield().input());
+                    });
+                }
+            }
+        )+
+    }
+}
+
+pins! {
+    USART0: (PE13, pe_modeh, mode13, pe_doutset, 13, PE12, pe_modeh, mode12) => 3,
+    USART0: (PC0, pc_model, mode0, pc_doutset, 0, PC1, pc_model, mode1) => 5,
+    USART1: (PC0, pc_model, mode0, pc_doutset, 0, PC1, pc_model, mode1) => 0,
+    LEUART0: (PB13, pb_modeh, mode13, pb_doutset, 13, PB14, pb_modeh, mode14) => 1,
+    LEUART0: (PF0, pf_model, mode0, pf_doutset, 0, PF1, pf_model, mode1) => 3,
+}
+
+/// Serial abstraction over USART peripheral in asynchronous mode
+pub struct Serial<USART, PINS> {
+    usart: USART,
+    pins: PINS,
+}
+
+/// Compute USART `CLKDIV` value for 16x oversampling,
+/// `CLKDIV = 256 * (fHFPERCLK / (16 * baudrate) - 1)`.
+fn clkdiv(hfperclk: u32, baudrate: u32) -> u32 {
+    let div = (16 * hfperclk + baudrate / 2) / baudrate;
+    div.saturating_sub(256) & 0x001f_ffc0
+}
+
+macro_rules! usart {
+    ($($USART:ident: ($usart:ident, $usartclken:ident),)+) => {
+        $(
+            impl<PINS: Pins<$USART>> Serial<$USART, PINS> {
+                /// Configure `USART` as UART with the given `baudrate`.
+                ///
+                /// The baudrate is derived from HFPERCLK as frozen in `clocks`.
+                pub fn $usart(usart: $USART, pins: PINS, baudrate: u32, clocks: &Clocks) -> Self {
+                    let cmu = unsafe { &*efm32::CMU::ptr() };
+
+                    critical_section::with(|_| {
+                        cmu.hfperclken0.modify(|_, w| w.$usartclken().set_bit());
+                    });
+
+                    pins.setup();
+
+                    usart.cmd.write(|w| {
+                        w.rxdis().set_bit()
+                         .txdis().set_bit()
+                         .clearrx().set_bit()
+                         .cleartx().set_bit()
+                    });
+
+                    // 8N1, 16x oversampling, asynchronous mode.
+                    usart.ctrl.reset();
+                    usart.frame.reset();
+                    usart.clkdiv.write(|w| unsafe { w.bits(clkdiv(clocks.hfperclk(), baudrate)) });
+
+                    usart.route.write(|w| unsafe {
+                        w.rxpen().set_bit()
+                         .txpen().set_bit()
+                         .location().bits(PINS::LOCATION)
+                    });
+
+                    usart.ifc.write(|w| unsafe { w.bits(0xffff_ffff) });
+                    usart.cmd.write(|w| w.rxen().set_bit().txen().set_bit());
+
+                    Serial { usart, pins }
+                }
+
+                /// Disable the USART and release the peripheral and pins.
+                pub fn free(self) -> ($USART, PINS) {
+                    self.usart.cmd.write(|w| w.rxdis().set_bit().txdis().set_bit());
+                    self.usart.route.reset();
+
+                    (self.usart, self.pins)
+                }
+            }
+
+            impl<PINS> serial::Read<u8> for Serial<$USART, PINS> {
+                type Error = Error;
+
+                fn read(&mut self) -> nb::Result<u8, Error> {
+                    let flags = self.usart.if_.read();
+
+                    let err = if flags.rxof().bit_is_set() {
+                        Some(Error::Overrun)
+                    } else if flags.perr().bit_is_set() {
+                        Some(Error::Parity)
+                    } else if flags.ferr().bit_is_set() {
+                        Some(Error::Framing)
+                    } else {
+                        None
+                    };
+
+                    if let Some(err) = err {
+                        self.usart.ifc.write(|w| {
+                            w.rxof().set_bit()
+                             .perr().set_bit()
+                             .ferr().set_bit()
+                        });
+                        return Err(nb::Error::Other(err));
+                    }
+
+                    if self.usart.status.read().rxdatav().bit_is_clear() {
+                        return Err(nb::Error::WouldBlock);
+                    }
+
+                    Ok(self.usart.rxdata.read().rxdata().bits())
+                }
+            }
+
+            impl<PINS> serial::Write<u8> for Serial<$USART, PINS> {
+                type Error = Error;
+
+                fn write(&mut self, word: u8) -> nb::Result<(), Error> {
+                    if self.usart.status.read().txbl().bit_is_clear() {
+                        return Err(nb::Error::WouldBlock);
+                    }
+
+                    self.usart.txdata.write(|w| unsafe { w.txdata().bits(word) });
+
+                    Ok(())
+                }
+
+                fn flush(&mut self) -> nb::Result<(), Error> {
+                    if self.usart.status.read().txc().bit_is_clear() {
+                        return Err(nb::Error::WouldBlock);
+                    }
+
+                    Ok(())
+                }
+            }
+
+            impl<PINS> fmt::Write for Serial<$USART, PINS> {
+                fn write_str(&mut self, s: &str) -> fmt::Result {
+                    use embedded_hal::serial::Write;
+
+                    for byte in s.bytes() {
+                        nb::block!(self.write(byte)).map_err(|_| fmt::Error)?;
+                    }
+
+                    Ok(())
+                }
+            }
+        )+
+    }
+}
+
+usart! {
+    USART0: (usart0, usart0),
+    USART1: (usart1, usart1),
+}
@@ -0,0 +1,502 @@
+//! In-application firmware update
+//!
+//! A new image, received any way the application likes (USB CDC, vendor
+//! requests, UART...), is written into a staging area in the upper half of
+//! the application flash. Once it's complete, its CRC and toboot config header
+//! are checked, and `Staged::activate` copies it over the running image and
+//! resets into it.
+//!
+//! ``` no_run
+//! # use tomu::update::Update;
+//! # let p = tomu::efm32hg::Peripherals::take().unwrap();
+//! # let chunks: &[&[u8]] = &[];
+//! # let expected_crc = 0;
+//! let mut update = Update::new(p.MSC).unwrap();
+//!
+//! for chunk in chunks {
+//!     update.write(chunk).unwrap();
+//! }
+//!
+//! // CRC-32 of the whole image, as computed by e.g. zlib's `crc32`.
+//! let staged = update.finish(expected_crc).unwrap();
+//! staged.activate();
+//! ```
+//!
+//! The running image has to fit in the lower half of the application flash,
+//! and new images in the upper half. Images that would lock toboot entry are
+//! refused unless `lock_entry_i_understand_this_locks_the_bootloader` is called,
+//! and so are erase masks reaching into toboot, the new image, or the
+//! persistent data region.
+//!
+//! `Update::with_flash` stages into any `NorFlash` instead, e.g. to check
+//! images on host, only `Update::new` can be activated.
+//!
+//! The copy itself can't be made power-loss safe, the image header is written
+//! last so toboot won't start a partially copied image, and will wait for a new
+//! one to be uploaded instead.
+use efm32::MSC;
+use embedded_storage::nor_flash::NorFlash;
+
+use crate::crc::crc32;
+use crate::flash::{self, Flash};
+use crate::layout::{self, PAGE_SIZE, TOBOOT_SECTORS};
+use crate::toboot::{
+    TobootConfig, TOBOOT_CONFIG_FLAG_AUTORUN, TOBOOT_CONFIG_FLAG_ENABLE_IRQ,
+    TOBOOT_LOCK_ENTRY_MAGIC, TOBOOT_V2_MAGIC,
+};
+
+/// Where toboot looks for the config header, and its size, in bytes.
+const CONFIG_OFFSET: u32 = tomu_image::CONFIG_OFFSET as u32;
+const CONFIG_SIZE: u32 = tomu_image::CONFIG_SIZE as u32;
+
+/// Update error
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error<E = flash::Error> {
+    /// Writing the staging area failed
+    Flash(E),
+    /// Running image reaches into the staging area
+    NoStagingArea,
+    /// Image doesn't fit in the staging area
+    TooLarge,
+    /// Image is too short to contain the config header
+    TooShort,
+    /// CRC of the staged image doesn't match
+    CrcMismatch { expected: u32, actual: u32 },
+    /// Config header magic is not `TOBOOT_V2_MAGIC`
+    InvalidMagic,
+    /// Config header start page doesn't match where the image is going
+    InvalidStart(u8),
+    /// Unknown bits are set in config flags
+    InvalidFlags(u8),
+    /// `lock_entry` is neither 0 nor `TOBOOT_LOCK_ENTRY_MAGIC`
+    InvalidLockEntry,
+    /// Image would lock toboot entry, and that wasn't acknowledged
+    LocksBootloader,
+    /// Erase mask touches toboot sectors, the new image itself,
+    /// or the persistent data region
+    InvalidEraseMask,
+}
+
+/// Image being written into the staging area
+pub struct Update<F = Flash> {
+    flash: F,
+    /// Bytes written so far, not counting `pending`
+    len: u32,
+    /// Bytes not yet written, flash is written a word at a time
+    pending: [u8; 4],
+    pending_len: usize,
+    /// Staging pages up to this offset are erased
+    erased: u32,
+    allow_lock_entry: bool,
+}
+
+impl Update {
+    /// Take `MSC` to write the staging area, the upper half of flash
+    /// between the application start and the persistent data region.
+    pub fn new(msc: MSC) -> Result<Self, Error> {
+        let pages = (layout::PERSISTENT_START - layout::APP_START) / PAGE_SIZE;
+        let staging = layout::PERSISTENT_START - pages / 2 * PAGE_SIZE;
+
+        if flash::image_end() > staging {
+            return Err(Error::NoStagingArea);
+        }
+
+        Ok(Update::with_flash(Flash::with_region(
+            msc,
+            staging..layout::PERSISTENT_START,
+        )))
+    }
+}
+
+impl<F: NorFlash> Update<F> {
+    /// Stage the image into `flash`, from its first byte on.
+    pub fn with_flash(flash: F) -> Self {
+        Update {
+            flash,
+            len: 0,
+            pending: [0xff; 4],
+            pending_len: 0,
+            erased: 0,
+            allow_lock_entry: false,
+        }
+    }
+
+    /// Largest image that can be staged.
+    pub fn capacity(&self) -> usize {
+        self.flash.capacity()
+    }
+
+    /// Accept images that lock toboot entry, after which toboot can no
+    /// longer be entered by shorting the outer pins.
+    pub fn lock_entry_i_understand_this_locks_the_bootloader(mut self) -> Self {
+        self.allow_lock_entry = true;
+        self
+    }
+
+    /// Append `data` to the staged image.
+    pub fn write(&mut self, mut data: &[u8]) -> Result<(), Error<F::Error>> {
+        let total = self.len as usize + self.pending_len + data.len();
+        if total > self.capacity() {
+            return Err(Error::TooLarge);
+        }
+
+        // Erase ahead, so whole words can be written.
+        let needed = ((total as u32 + 3) & !3).div_ceil(PAGE_SIZE) * PAGE_SIZE;
+        if needed > self.erased {
+            self.flash
+                .erase(self.erased, needed)
+                .map_err(Error::Flash)?;
+            self.erased = needed;
+        }
+
+        if self.pending_len > 0 {
+            let take = (4 - self.pending_len).min(data.len());
+            self.pending[self.pending_len..self.pending_len + take].copy_from_slice(&data[..take]);
+            self.pending_len += take;
+            data = &data[take..];
+
+            if self.pending_len < 4 {
+                return Ok(());
+            }
+
+            self.flash
+                .write(self.len, &self.pending)
+                .map_err(Error::Flash)?;
+            self.len += 4;
+            self.pending_len = 0;
+        }
+
+        let aligned = data.len() & !3;
+        if aligned > 0 {
+            self.flash
+                .write(self.len, &data[..aligned])
+                .map_err(Error::Flash)?;
+            self.len += aligned as u32;
+        }
+
+        self.pending_len = data.len() - aligned;
+        self.pending = [0xff; 4];
+        self.pending[..self.pending_len].copy_from_slice(&data[aligned..]);
+
+        Ok(())
+    }
+
+    /// Complete the staged image, and check it against `crc` (CRC-32 as
+    /// computed by zlib) and its toboot config header.
+    pub fn finish(mut self, crc: u32) -> Result<Staged<F>, Error<F::Error>> {
+        if self.pending_len > 0 {
+            self.flash
+                .write(self.len, &self.pending)
+                .map_err(Error::Flash)?;
+            self.len += self.pending_len as u32;
+            self.pending_len = 0;
+        }
+
+        let mut actual = 0;
+        let mut buf = [0u8; 64];
+        let mut offset = 0;
+        while offset < self.len {
+            let chunk = (self.len - offset).min(buf.len() as u32) as usize;
+            self.flash
+                .read(offset, &mut buf[..chunk])
+                .map_err(Error::Flash)?;
+            actual = crc32(actual, &buf[..chunk]);
+            offset += chunk as u32;
+        }
+
+        if actual != crc {
+            return Err(Error::CrcMismatch {
+                expected: crc,
+                actual,
+            });
+        }
+
+        if !layout::BOOTLOADER {
+            return Ok(Staged {
+                flash: self.flash,
+                len: self.len,
+                config: None,
+            });
+        }
+
+        if self.len < CONFIG_OFFSET + CONFIG_SIZE {
+            return Err(Error::TooShort);
+        }
+
+        let mut header = [0u8; CONFIG_SIZE as usize];
+        self.flash
+            .read(CONFIG_OFFSET, &mut header)
+            .map_err(Error::Flash)?;
+        let config = TobootConfig::from_bytes(&header);
+
+        self.validate(&config)?;
+
+        Ok(Staged {
+            flash: self.flash,
+            len: self.len,
+            config: Some(config),
+        })
+    }
+
+    fn validate(&self, config: &TobootConfig) -> Result<(), Error<F::Error>> {
+        if config.magic != TOBOOT_V2_MAGIC {
+            return Err(Error::InvalidMagic);
+        }
+
+        if u32::from(config.start) != layout::APP_START_PAGE {
+            return Err(Error::InvalidStart(config.start));
+        }
+
+        if config.config & !(TOBOOT_CONFIG_FLAG_ENABLE_IRQ | TOBOOT_CONFIG_FLAG_AUTORUN) != 0 {
+            return Err(Error::InvalidFlags(config.config));
+        }
+
+        match config.lock_entry {
+            0 => {}
+            TOBOOT_LOCK_ENTRY_MAGIC if self.allow_lock_entry => {}
+            TOBOOT_LOCK_ENTRY_MAGIC => return Err(Error::LocksBootloader),
+            _ => return Err(Error::InvalidLockEntry),
+        }
+
+        let end = (layout::APP_START + self.len).div_ceil(PAGE_SIZE);
+        let image_mask = sector_mask(layout::APP_START_PAGE..end);
+        let toboot_mask = sector_mask(0..TOBOOT_SECTORS);
+        let persistent_mask =
+            sector_mask(layout::PERSISTENT_START / PAGE_SIZE..layout::PERSISTENT_END / PAGE_SIZE);
+        if config.erase_mask() & (image_mask | toboot_mask | persistent_mask) != 0 {
+            return Err(Error::InvalidEraseMask);
+        }
+
+        Ok(())
+    }
+}
+
+/// Erase mask bits for `sectors`.
+fn sector_mask(sectors: core::ops::Range<u32>) -> u64 {
+    sectors.fold(0, |mask, sector| mask | 1 << sector)
+}
+
+/// Complete and verified image in the staging area
+pub struct Staged<F = Flash> {
+    flash: F,
+    len: u32,
+    config: Option<TobootConfig>,
+}
+
+impl<F> Staged<F> {
+    /// Config header of the staged image, `None` without toboot.
+    pub fn config(&self) -> Option<TobootConfig> {
+        self.config
+    }
+
+    /// Image size in bytes.
+    pub fn size(&self) -> usize {
+        self.len as usize
+    }
+}
+
+impl Staged<Flash> {
+    /// Give up on the staged image, and get `MSC` back.
+    pub fn cancel(self) -> MSC {
+        self.flash.free()
+    }
+
+    /// Copy the staged image over the running one, and reset into it.
+    ///
+    /// The header is given the next generation number and its hash is
+    /// computed, the same way toboot does when uploading over DFU.
+    /// Sectors in the new image's erase mask are erased afterwards.
+    pub fn activate(self) -> ! {
+        let (header, erase_mask) = match self.config {
+            Some(mut config) => {
+                config.reserved_gen = crate::toboot::current_config().generation().wrapping_add(1);
+                config.reserved_hash = config.compute_hash();
+
+                let bytes = config.to_bytes();
+                let mut header = [0u32; 6];
+                for (word, bytes) in header.iter_mut().zip(bytes.chunks_exact(4)) {
+                    *word = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+                }
+
+                (Some(header), config.erase_mask())
+            }
+            None => (None, 0),
+        };
+
+        let region = self.flash.region();
+
+        cortex_m::interrupt::disable();
+
+        // Everything past this point runs from RAM, the flash it's running
+        // from is about to be erased.
+        unsafe {
+            copy_and_reset(&CopyParams {
+                src: region.start,
+                dst: layout::APP_START,
+                len: self.len,
+                header: header.unwrap_or([0; 6]),
+                patch_header: header.is_some(),
+                erase_mask_lo: erase_mask as u32,
+                erase_mask_hi: (erase_mask >> 32) as u32,
+            })
+        }
+    }
+}
+
+/// Parameters for `copy_and_reset`, 32-bit only since 64-bit
+/// arithmetic would call into compiler builtins in flash.
+struct CopyParams {
+    src: u32,
+    dst: u32,
+    len: u32,
+    header: [u32; 6],
+    patch_header: bool,
+    erase_mask_lo: u32,
+    erase_mask_hi: u32,
+}
+
+const MSC_WRITECTRL: u32 = 0x400c_0008;
+const MSC_WRITECMD: u32 = 0x400c_000c;
+const MSC_ADDRB: u32 = 0x400c_0010;
+const MSC_WDATA: u32 = 0x400c_0018;
+const MSC_STATUS: u32 = 0x400c_001c;
+const MSC_LOCK: u32 = 0x400c_003c;
+
+const MSC_WRITECTRL_WREN: u32 = 1 << 0;
+const MSC_WRITECMD_LADDRIM: u32 = 1 << 0;
+const MSC_WRITECMD_ERASEPAGE: u32 = 1 << 1;
+const MSC_WRITECMD_WRITEONCE: u32 = 1 << 3;
+const MSC_STATUS_BUSY: u32 = 1 << 0;
+const MSC_STATUS_WDATAREADY: u32 = 1 << 3;
+
+const SCB_AIRCR: u32 = 0xe000_ed0c;
+const SCB_AIRCR_SYSRESETREQ: u32 = 0x05fa_0004;
+
+const PAGE_SHIFT: u32 = PAGE_SIZE.trailing_zeros();
+const CONFIG_END: u32 = CONFIG_OFFSET + CONFIG_SIZE;
+
+/// Erase destination pages, copy the image with its first page last,
+/// erase the new image's erase mask, then reset.
+///
+/// Lives in `.data`, so it's copied to RAM on startup. It must not call
+/// anything, even in debug builds: memory is only accessed through the
+/// `asm!` helpers below, since `read_volatile` and friends have out of
+/// line precondition checks, and arithmetic is wrapping or by shifts, so
+/// there are no overflow checks or division builtins.
+#[inline(never)]
+#[link_section = ".data.tomu_update"]
+#[allow(clippy::manual_range_contains)]
+unsafe fn copy_and_reset(copy: &CopyParams) -> ! {
+    store(MSC_LOCK, 0x1b71);
+    store(MSC_WRITECTRL, MSC_WRITECTRL_WREN);
+
+    let pages = copy.len.wrapping_add(PAGE_SIZE - 1) >> PAGE_SHIFT;
+
+    // Erase everything first, an erased first page has no valid header.
+    let mut page = 0;
+    while page < pages {
+        erase_page(copy.dst.wrapping_add(page << PAGE_SHIFT));
+        page = page.wrapping_add(1);
+    }
+
+    // Copy from the second page on, then the first one.
+    let words = copy.len.wrapping_add(3) >> 2;
+    let first_page_words = PAGE_SIZE >> 2;
+    let mut pass = 0u32;
+    while pass < 2 {
+        let (mut word, end) = if pass == 0 {
+            (first_page_words, words)
+        } else {
+            (0, if words < first_page_words { words } else { first_page_words })
+        };
+
+        while word < end {
+            let offset = word << 2;
+            let mut data = load(copy.src.wrapping_add(offset));
+
+            if copy.patch_header && offset >= CONFIG_OFFSET && offset < CONFIG_END {
+                // No indexing, bounds checks would panic into flash.
+                let header = &copy.header as *const [u32; 6] as u32;
+                data = load(header.wrapping_add(offset.wrapping_sub(CONFIG_OFFSET)));
+            }
+
+            write_word(copy.dst.wrapping_add(offset), data);
+            word = word.wrapping_add(1);
+        }
+
+        pass = pass.wrapping_add(1);
+    }
+
+    // Sectors the new image asked to be erased, already checked against
+    // toboot sectors and the new image itself.
+    let mut sector = 0;
+    let mut mask = copy.erase_mask_lo;
+    while sector < 64 {
+        if sector == 32 {
+            mask = copy.erase_mask_hi;
+        }
+
+        if mask & 1 != 0 {
+            erase_page(sector << PAGE_SHIFT);
+        }
+
+        mask >>= 1;
+        sector = sector.wrapping_add(1);
+    }
+
+    store(MSC_WRITECTRL, 0);
+    store(MSC_LOCK, 0);
+
+    // Same as `toboot::reboot_to_application`, clear boot token and reset.
+    store(0x2000_0000, 0);
+    store(SCB_AIRCR, SCB_AIRCR_SYSRESETREQ);
+
+    // Wait for the reset. Not `cortex_m::asm::nop`, which is a call into
+    // flash on stable.
+    loop {
+        core::arch::asm!("nop", options(nomem, nostack));
+    }
+}
+
+/// Erase the flash page at `address`, for `copy_and_reset`.
+#[inline(always)]
+unsafe fn erase_page(address: u32) {
+    store(MSC_ADDRB, address);
+    store(MSC_WRITECMD, MSC_WRITECMD_LADDRIM);
+    store(MSC_WRITECMD, MSC_WRITECMD_ERASEPAGE);
+    while load(MSC_STATUS) & MSC_STATUS_BUSY != 0 {}
+}
+
+/// Write one word of flash at `address`, for `copy_and_reset`.
+#[inline(always)]
+unsafe fn write_word(address: u32, data: u32) {
+    store(MSC_ADDRB, address);
+    store(MSC_WRITECMD, MSC_WRITECMD_LADDRIM);
+    while load(MSC_STATUS) & MSC_STATUS_WDATAREADY == 0 {}
+    store(MSC_WDATA, data);
+    store(MSC_WRITECMD, MSC_WRITECMD_WRITEONCE);
+    while load(MSC_STATUS) & MSC_STATUS_BUSY != 0 {}
+}
+
+#[inline(always)]
+unsafe fn load(address: u32) -> u32 {
+    #[cfg(target_arch = "arm")]
+    {
+        let data;
+        core::arch::asm!("ldr {}, [{}]", out(reg) data, in(reg) address, options(nostack, readonly));
+        data
+    }
+
+    // Host builds, for the tests, never get to run it.
+    #[cfg(not(target_arch = "arm"))]
+    core::ptr::read_volatile(address as usize as *const u32)
+}
+
+#[inline(always)]
+unsafe fn store(address: u32, data: u32) {
+    #[cfg(target_arch = "arm")]
+    core::arch::asm!("str {}, [{}]", in(reg) data, in(reg) address, options(nostack));
+
+    #[cfg(not(target_arch = "arm"))]
+    core::ptr::write_volatile(address as usize as *mut u32, data);
+}
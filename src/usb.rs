@@ -1 +1,75 @@
+//! USB device support for tomu
+//!
+//! The USB core in efm32hg is a Synopsys DesignWare OTG core (full speed),
+//! so the heavy lifting of `usb_device::bus::UsbBus` is delegated to
+//! `synopsys-usb-otg`. This module only provides the efm32hg specific glue:
+//! clocking, PHY routing, and where the core registers live.
+use synopsys_usb_otg::UsbPeripheral;
 
+use crate::clocks::Clocks;
+
+/// Address of the OTG core registers (`GOTGCTL`), which are located
+/// at offset 0x3c000 from the USB peripheral base (0x400c4000).
+pub(crate) const USB_CORE_BASE: usize = 0x4010_0000;
+
+/// USB peripheral wrapper, takes ownership of efm32 `USB` peripheral.
+pub struct USB {
+    pub usb: efm32::USB,
+    hfcoreclk: u32,
+}
+
+impl USB {
+    /// Wrap `USB` peripheral, HFCORECLK frequency from `clocks` is used to
+    /// compute the USB turnaround time. USB requires HFCORECLK to be at
+    /// least 14 MHz, and HFCLK to run from USHFRCO (see `Tomu::builder`).
+    pub fn new(usb: efm32::USB, clocks: &Clocks) -> Self {
+        Self { usb, hfcoreclk: clocks.hfcoreclk() }
+    }
+
+    /// Release the underlying `USB` peripheral.
+    pub fn free(self) -> efm32::USB {
+        self.usb
+    }
+}
+
+unsafe impl Sync for USB {}
+
+unsafe impl UsbPeripheral for USB {
+    const REGISTERS: *const () = USB_CORE_BASE as *const ();
+
+    const HIGH_SPEED: bool = false;
+
+    // efm32hg has 1.5 KiB of FIFO RAM shared by all endpoints.
+    const FIFO_DEPTH_WORDS: usize = 384;
+
+    // Control endpoint plus 3 IN and 3 OUT endpoints.
+    const ENDPOINT_COUNT: usize = 4;
+
+    fn enable() {
+        let cmu = unsafe { &*efm32::CMU::ptr() };
+        let usb = unsafe { &*efm32::USB::ptr() };
+
+        critical_section::with(|_| {
+            // USB core needs its own 48 MHz clock (USBC), tomu has no crystal
+            // so this will have to be sourced from USHFRCO.
+            cmu.oscencmd.write(|w| w.ushfrcoen().set_bit());
+            while cmu.status.read().ushfrcordy().bit_is_clear() {}
+
+            cmu.cmd.write(|w| w.usbcclksel().ushfrco());
+            while cmu.status.read().usbcushfrcosel().bit_is_clear() {}
+
+            cmu.hfcoreclken0
+                .modify(|_, w| w.usb().set_bit().usbc().set_bit());
+
+            // Route D+/D- to the internal PHY.
+            usb.route.write(|w| w.phypen().set_bit());
+        });
+    }
+
+    fn ahb_frequency_hz(&self) -> u32 {
+        self.hfcoreclk
+    }
+}
+
+/// `usb_device::bus::UsbBus` implementation for tomu
+pub type UsbBus = synopsys_usb_otg::UsbBus<USB>;
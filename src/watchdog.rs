@@ -0,0 +1,147 @@
+//! Watchdog timer (WDOG) support for tomu
+//!
+//! WDOG is enabled out of reset, from ULFRCO with its longest period
+//! (about 256 seconds), so applications either feed it or disable it.
+//! It runs from the low frequency oscillator in its `WdogClock` token,
+//! `Tomu` takes `lf_clocks.wdog` when one was configured, ULFRCO otherwise:
+//!
+//! ``` no_run
+//! # use core::time::Duration;
+//! # use tomu::clocks::{LfConfig, LfOscillator};
+//! # let p = tomu::efm32hg::Peripherals::take().unwrap();
+//! let mut tomu = tomu::Tomu::builder()
+//!     .lf_clocks(LfConfig::new().wdog(LfOscillator::Lfrco))
+//!     .build(p.CMU, p.WDOG, p.GPIO, p.SYST);
+//!
+//! tomu.watchdog.start(Duration::from_millis(500));
+//! loop {
+//!     // ...
+//!     tomu.watchdog.feed();
+//! }
+//! ```
+use core::time::Duration;
+
+use efm32::WDOG;
+
+use crate::clocks::{LfOscillator, WdogClock};
+
+const CTRL_EN: u32 = 1 << 0;
+const CTRL_PERSEL_SHIFT: u32 = 8;
+const CTRL_PERSEL_MASK: u32 = 0xf << CTRL_PERSEL_SHIFT;
+const CTRL_CLKSEL_SHIFT: u32 = 12;
+const CTRL_CLKSEL_MASK: u32 = 0x3 << CTRL_CLKSEL_SHIFT;
+
+const CMD_CLEAR: u32 = 1 << 0;
+
+/// Longest period, `2^(3 + PERSEL) + 1` cycles.
+const MAX_PERSEL: u32 = 15;
+
+/// Watchdog driver
+pub struct Watchdog {
+    wdog: WDOG,
+    clock: WdogClock,
+}
+
+impl Watchdog {
+    /// Take `WDOG` and clock it from `clock`. Whether it's running,
+    /// and its period, are left as they are.
+    ///
+    /// If the watchdog has been locked, its clock can't be changed anymore.
+    pub fn new(wdog: WDOG, clock: WdogClock) -> Self {
+        let clksel = match clock.source() {
+            LfOscillator::Ulfrco => 0,
+            LfOscillator::Lfrco => 1,
+            LfOscillator::Lfxo => 2,
+        };
+
+        let mut watchdog = Watchdog { wdog, clock };
+
+        // CLKSEL may only change while stopped, and each write has to
+        // reach the watchdog clock domain before the next one.
+        let enabled = watchdog.wdog.ctrl.read().bits() & CTRL_EN != 0;
+        watchdog.modify_ctrl(CTRL_EN, 0);
+        watchdog.modify_ctrl(CTRL_CLKSEL_MASK, clksel << CTRL_CLKSEL_SHIFT);
+        if enabled {
+            watchdog.feed();
+            watchdog.modify_ctrl(CTRL_EN, CTRL_EN);
+        }
+
+        watchdog
+    }
+
+    /// Watchdog clock frequency in Hz.
+    pub fn hz(&self) -> u32 {
+        self.clock.hz()
+    }
+
+    /// Enable the watchdog, resetting the device unless fed within `timeout`.
+    ///
+    /// Periods are powers of 2 clock cycles, the shortest one not below
+    /// `timeout` is picked, up to 2^18 cycles.
+    pub fn start(&mut self, timeout: Duration) {
+        let cycles = timeout.as_millis() * u128::from(self.hz()) / 1000;
+        let persel = (0..MAX_PERSEL)
+            .find(|persel| (1u128 << (3 + persel)) + 1 >= cycles)
+            .unwrap_or(MAX_PERSEL);
+
+        self.feed();
+        self.modify_ctrl(
+            CTRL_PERSEL_MASK | CTRL_EN,
+            (persel << CTRL_PERSEL_SHIFT) | CTRL_EN,
+        );
+    }
+
+    /// Restart the watchdog period.
+    pub fn feed(&mut self) {
+        self.sync();
+        self.wdog.cmd.write(|w| unsafe { w.bits(CMD_CLEAR) });
+    }
+
+    /// Stop the watchdog.
+    pub fn disable(&mut self) {
+        self.modify_ctrl(CTRL_EN, 0);
+    }
+
+    /// Release `WDOG` and its clock.
+    pub fn free(self) -> (WDOG, WdogClock) {
+        (self.wdog, self.clock)
+    }
+
+    fn modify_ctrl(&mut self, mask: u32, bits: u32) {
+        self.sync();
+        self.wdog
+            .ctrl
+            .modify(|r, w| unsafe { w.bits((r.bits() & !mask) | bits) });
+    }
+
+    /// Wait until earlier writes have reached the watchdog clock domain.
+    fn sync(&self) {
+        while self.wdog.syncbusy.read().bits() != 0 {}
+    }
+}
+
+#[cfg(feature = "unproven")]
+impl embedded_hal::watchdog::Watchdog for Watchdog {
+    fn feed(&mut self) {
+        Watchdog::feed(self);
+    }
+}
+
+#[cfg(feature = "unproven")]
+impl embedded_hal::watchdog::WatchdogEnable for Watchdog {
+    type Time = Duration;
+
+    fn start<T>(&mut self, period: T)
+    where
+        T: Into<Duration>,
+    {
+        Watchdog::start(self, period.into());
+    }
+}
+
+#[cfg(feature = "unproven")]
+impl embedded_hal::watchdog::WatchdogDisable for Watchdog {
+    fn disable(&mut self) {
+        Watchdog::disable(self);
+    }
+}
@@ -0,0 +1,91 @@
+//! Helpers shared by the host tests.
+use embedded_storage::nor_flash::{
+    check_erase, check_read, check_write, ErrorType, NorFlash, NorFlashErrorKind, ReadNorFlash,
+};
+use tomu::layout::PAGE_SIZE;
+
+/// NOR flash in RAM: erase sets bits, write can only clear them.
+/// Power loss is simulated by failing every operation after `budget` writes.
+pub struct RamFlash {
+    pub data: Vec<u8>,
+    budget: Option<usize>,
+}
+
+impl RamFlash {
+    /// Erased flash of `pages` pages.
+    pub fn new(pages: usize) -> Self {
+        RamFlash {
+            data: vec![0xff; pages * PAGE_SIZE as usize],
+            budget: None,
+        }
+    }
+
+    /// Lose power after `writes` more word writes.
+    #[allow(dead_code)]
+    pub fn lose_power_after(&mut self, writes: usize) {
+        self.budget = Some(writes);
+    }
+
+    #[allow(dead_code)]
+    pub fn restore_power(&mut self) {
+        self.budget = None;
+    }
+}
+
+impl ErrorType for RamFlash {
+    type Error = NorFlashErrorKind;
+}
+
+impl ReadNorFlash for RamFlash {
+    const READ_SIZE: usize = 1;
+
+    fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+        check_read(self, offset, bytes.len())?;
+        bytes.copy_from_slice(&self.data[offset as usize..offset as usize + bytes.len()]);
+        Ok(())
+    }
+
+    fn capacity(&self) -> usize {
+        self.data.len()
+    }
+}
+
+impl NorFlash for RamFlash {
+    const WRITE_SIZE: usize = 4;
+    const ERASE_SIZE: usize = 1024;
+
+    fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+        check_erase(self, from, to)?;
+        if self.budget == Some(0) {
+            return Err(NorFlashErrorKind::Other);
+        }
+
+        self.data[from as usize..to as usize].fill(0xff);
+        Ok(())
+    }
+
+    fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+        check_write(self, offset, bytes.len())?;
+
+        for (i, word) in bytes.chunks(4).enumerate() {
+            match &mut self.budget {
+                Some(0) => return Err(NorFlashErrorKind::Other),
+                Some(budget) => *budget -= 1,
+                None => {}
+            }
+
+            let at = offset as usize + 4 * i;
+            for (cell, byte) in self.data[at..at + 4].iter_mut().zip(word) {
+                assert_eq!(
+                    *byte & !*cell,
+                    0,
+                    "writing 1 to programmed bit at {:#x}",
+                    at
+                );
+                *cell &= *byte;
+            }
+        }
+
+        Ok(())
+    }
+}
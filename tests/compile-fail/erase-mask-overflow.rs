@@ -0,0 +1,9 @@
+extern crate tomu_macros;
+
+use tomu_macros::toboot_config;
+
+toboot_config! {
+    erase_mask_hi: 0x1_0000_0000, //~ ERROR erase mask 0x100000000 overflows 32 bit
+}
+
+fn main() {}
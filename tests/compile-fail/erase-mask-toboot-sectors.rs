@@ -0,0 +1,9 @@
+extern crate tomu_macros;
+
+use tomu_macros::toboot_config;
+
+toboot_config! {
+    erase_mask_lo: 0x0001_8000, //~ ERROR erase_mask_lo 0x00018000 sets bits for sectors 0-15
+}
+
+fn main() {}
@@ -0,0 +1,9 @@
+extern crate tomu_macros;
+
+use tomu_macros::toboot_config;
+
+toboot_config! {
+    erase: [0x8000..=0xffff_ffff_ffff_ffff], //~ ERROR flash range end address overflows
+}
+
+fn main() {}
@@ -0,0 +1,9 @@
+extern crate tomu_macros;
+
+use tomu_macros::toboot_config;
+
+toboot_config! {
+    erase: [0x8100..0xa000], //~ ERROR flash range 0x8100..0xa000 is not page-aligned
+}
+
+fn main() {}
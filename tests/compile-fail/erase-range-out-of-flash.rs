@@ -0,0 +1,9 @@
+extern crate tomu_macros;
+
+use tomu_macros::toboot_config;
+
+toboot_config! {
+    erase: [0xf000..0x10400], //~ ERROR flash range 0xf000..0x10400 exceeds flash size (0x10000)
+}
+
+fn main() {}
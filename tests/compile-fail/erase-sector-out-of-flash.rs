@@ -0,0 +1,9 @@
+extern crate tomu_macros;
+
+use tomu_macros::toboot_config;
+
+toboot_config! {
+    erase: [sector(64)], //~ ERROR sector 64 is out of flash, valid sectors are 0-63
+}
+
+fn main() {}
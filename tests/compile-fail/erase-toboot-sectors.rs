@@ -0,0 +1,9 @@
+extern crate tomu_macros;
+
+use tomu_macros::toboot_config;
+
+toboot_config! {
+    erase: [0x3c00..0x4400], //~ ERROR sectors 0-15 (0x0..0x4000) are owned by toboot and cannot be erased
+}
+
+fn main() {}
@@ -0,0 +1,10 @@
+extern crate tomu_macros;
+
+use tomu_macros::toboot_config;
+
+toboot_config! {
+    config: [autorun_enable],
+    i_understand_this_locks_the_bootloader: true, //~ ERROR acknowledges `lock_entry: true`, which is not set
+}
+
+fn main() {}
@@ -0,0 +1,10 @@
+extern crate tomu_macros;
+
+use tomu_macros::toboot_config;
+
+toboot_config! {
+    lock_entry: true, //~ ERROR setting `lock_entry` to `true` will _LOCK_ you out
+    i_understand_this_locks_the_bootloader: false,
+}
+
+fn main() {}
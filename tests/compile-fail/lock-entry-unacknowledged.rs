@@ -0,0 +1,10 @@
+extern crate tomu_macros;
+
+use tomu_macros::toboot_config;
+
+toboot_config! {
+    config: [autorun_enable],
+    lock_entry: true, //~ ERROR setting `lock_entry` to `true` will _LOCK_ you out
+}
+
+fn main() {}
@@ -0,0 +1,24 @@
+//! `toboot_config!` compile errors, run on host:
+//! `cargo +nightly test --test compiletest --target x86_64-unknown-linux-gnu`
+//!
+//! Each file in `tests/compile-fail` has to fail to compile with the
+//! errors annotated with `//~ ERROR`.
+extern crate compiletest_rs as compiletest;
+
+use std::path::PathBuf;
+
+fn run_mode(mode: &'static str) {
+    let mut config = compiletest::Config::default();
+
+    config.mode = mode.parse().expect("invalid mode");
+    config.src_base = PathBuf::from(format!("tests/{}", mode));
+    config.link_deps();
+    config.clean_rmeta();
+
+    compiletest::run_tests(&config);
+}
+
+#[test]
+fn compile_fail() {
+    run_mode("compile-fail");
+}
@@ -0,0 +1,203 @@
+//! Settings store tests, run on host against a RAM-backed flash:
+//! `cargo test --test settings --target x86_64-unknown-linux-gnu`
+mod common;
+
+use common::RamFlash;
+use tomu::layout::PAGE_SIZE;
+use tomu::settings::{Error, Settings};
+
+fn get(settings: &mut Settings<RamFlash>, key: u16) -> Option<Vec<u8>> {
+    let mut buf = [0u8; 1024];
+    let len = settings.get(key, &mut buf).unwrap()?;
+    Some(buf[..len].to_vec())
+}
+
+#[test]
+fn set_and_get() {
+    let mut settings = Settings::new(RamFlash::new(2)).unwrap();
+
+    settings.set(1, b"hello").unwrap();
+    settings.set(2, &[]).unwrap();
+    settings.set(1, b"world!").unwrap();
+
+    assert_eq!(get(&mut settings, 1).as_deref(), Some(&b"world!"[..]));
+    assert_eq!(get(&mut settings, 2).as_deref(), Some(&[][..]));
+    assert_eq!(get(&mut settings, 3), None);
+}
+
+#[test]
+fn remove() {
+    let mut settings = Settings::new(RamFlash::new(2)).unwrap();
+
+    settings.set(1, b"hello").unwrap();
+    settings.remove(1).unwrap();
+    settings.remove(2).unwrap();
+
+    assert_eq!(get(&mut settings, 1), None);
+}
+
+#[test]
+fn persists_across_mount() {
+    let mut settings = Settings::new(RamFlash::new(2)).unwrap();
+    settings.set(7, b"keymap").unwrap();
+
+    let mut settings = Settings::new(settings.free()).unwrap();
+    assert_eq!(get(&mut settings, 7).as_deref(), Some(&b"keymap"[..]));
+
+    settings.set(8, b"threshold").unwrap();
+    let mut settings = Settings::new(settings.free()).unwrap();
+    assert_eq!(get(&mut settings, 7).as_deref(), Some(&b"keymap"[..]));
+    assert_eq!(get(&mut settings, 8).as_deref(), Some(&b"threshold"[..]));
+}
+
+#[test]
+fn compaction() {
+    let mut settings = Settings::new(RamFlash::new(2)).unwrap();
+    settings.set(1, b"serial-0001").unwrap();
+
+    // Enough writes to go around both pages several times.
+    for i in 0u32..500 {
+        settings.set(2, &i.to_le_bytes()).unwrap();
+    }
+    settings.remove(1).unwrap();
+    settings.set(3, &[0xaa; 100]).unwrap();
+
+    let mut settings = Settings::new(settings.free()).unwrap();
+    assert_eq!(get(&mut settings, 1), None);
+    assert_eq!(
+        get(&mut settings, 2).as_deref(),
+        Some(&499u32.to_le_bytes()[..])
+    );
+    assert_eq!(get(&mut settings, 3).as_deref(), Some(&[0xaa; 100][..]));
+}
+
+#[test]
+fn full() {
+    let mut settings = Settings::new(RamFlash::new(2)).unwrap();
+    let value = [0x55; 300];
+
+    settings.set(1, &value).unwrap();
+    settings.set(2, &value).unwrap();
+    settings.set(3, &value).unwrap();
+    assert_eq!(settings.set(4, &value), Err(Error::Full));
+
+    // Failed compaction leaves existing settings alone.
+    let mut settings = Settings::new(settings.free()).unwrap();
+    assert_eq!(get(&mut settings, 3).as_deref(), Some(&value[..]));
+    assert_eq!(get(&mut settings, 4), None);
+}
+
+#[test]
+fn value_too_large() {
+    let mut settings = Settings::new(RamFlash::new(2)).unwrap();
+    let value = [0; Settings::<RamFlash>::MAX_VALUE_LEN + 1];
+
+    assert_eq!(settings.set(1, &value), Err(Error::ValueTooLarge));
+}
+
+#[test]
+fn buffer_too_small() {
+    let mut settings = Settings::new(RamFlash::new(2)).unwrap();
+    settings.set(1, b"hello").unwrap();
+
+    assert_eq!(settings.get(1, &mut [0; 4]), Err(Error::BufferTooSmall(5)));
+}
+
+#[test]
+fn power_loss_while_writing() {
+    // Cut power at every possible point of an update, the store must come
+    // back with either the old or the new value.
+    for writes in 0..8 {
+        let mut settings = Settings::new(RamFlash::new(2)).unwrap();
+        settings.set(1, b"old value").unwrap();
+
+        let mut flash = settings.free();
+        flash.lose_power_after(writes);
+        let mut settings = Settings::new(flash).unwrap();
+        let result = settings.set(1, b"new value");
+
+        let mut flash = settings.free();
+        flash.restore_power();
+        let mut settings = Settings::new(flash).unwrap();
+        let value = get(&mut settings, 1).unwrap();
+
+        if result.is_ok() {
+            assert_eq!(value, b"new value");
+        } else {
+            assert!(value == b"old value" || value == b"new value");
+        }
+
+        // And still usable afterwards.
+        settings.set(2, b"after").unwrap();
+        assert_eq!(get(&mut settings, 2).as_deref(), Some(&b"after"[..]));
+    }
+}
+
+#[test]
+fn power_loss_while_compacting() {
+    let fill = |settings: &mut Settings<RamFlash>| {
+        settings.set(1, b"keep me").unwrap();
+        // Fills the page up to the last record.
+        for i in 0u32..83 {
+            settings.set(2, &i.to_le_bytes()).unwrap();
+        }
+    };
+
+    // Cut power after 0, 1, 2... writes until the compaction goes through.
+    let mut settings = Settings::new(RamFlash::new(2)).unwrap();
+    fill(&mut settings);
+    let mut writes = 0;
+    loop {
+        let mut flash = settings.free();
+        flash.lose_power_after(writes);
+        settings = Settings::new(flash).unwrap();
+
+        let result = settings.set(2, &1000u32.to_le_bytes());
+
+        let mut flash = settings.free();
+        flash.restore_power();
+        settings = Settings::new(flash).unwrap();
+
+        assert_eq!(get(&mut settings, 1).as_deref(), Some(&b"keep me"[..]));
+        let value = get(&mut settings, 2).unwrap();
+        assert!(value == 82u32.to_le_bytes() || value == 1000u32.to_le_bytes());
+
+        if result.is_ok() {
+            break;
+        }
+
+        writes += 1;
+        settings = Settings::new(RamFlash::new(2)).unwrap();
+        fill(&mut settings);
+    }
+}
+
+#[test]
+fn corrupted_record_is_ignored() {
+    let mut settings = Settings::new(RamFlash::new(2)).unwrap();
+    settings.set(1, b"good").unwrap();
+    settings.set(1, b"flipped").unwrap();
+
+    // Clear a bit in the second record's value.
+    let mut flash = settings.free();
+    let at = flash
+        .data
+        .windows(7)
+        .position(|window| window == b"flipped")
+        .unwrap();
+    flash.data[at] &= !0x02;
+
+    let mut settings = Settings::new(flash).unwrap();
+    assert_eq!(get(&mut settings, 1).as_deref(), Some(&b"good"[..]));
+
+    settings.set(1, b"fixed").unwrap();
+    assert_eq!(get(&mut settings, 1).as_deref(), Some(&b"fixed"[..]));
+}
+
+#[test]
+fn too_small() {
+    let mut flash = RamFlash::new(2);
+    flash.data.truncate(PAGE_SIZE as usize);
+
+    assert!(matches!(Settings::new(flash), Err(Error::TooSmall)));
+}
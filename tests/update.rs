@@ -0,0 +1,132 @@
+//! Update validation tests, run on host against a RAM-backed flash:
+//! `cargo test --test update --target x86_64-unknown-linux-gnu`
+#![cfg(not(feature = "no-bootloader"))]
+
+mod common;
+
+use common::RamFlash;
+use embedded_storage::nor_flash::NorFlashErrorKind;
+use tomu::crc::crc32;
+use tomu::layout::{self, PAGE_SIZE};
+use tomu::toboot::TobootConfig;
+use tomu::update::{Error, Update};
+
+/// Where toboot looks for the config header.
+const CONFIG_OFFSET: usize = 0x94;
+
+/// 2KiB image with `config` as its toboot header.
+fn image(config: TobootConfig) -> Vec<u8> {
+    let mut image: Vec<u8> = (0..2048).map(|i| i as u8).collect();
+    image[CONFIG_OFFSET..CONFIG_OFFSET + 24].copy_from_slice(&config.to_bytes());
+    image
+}
+
+fn stage(
+    mut update: Update<RamFlash>,
+    image: &[u8],
+) -> Result<TobootConfig, Error<NorFlashErrorKind>> {
+    // Odd chunks, so words are split across writes.
+    for chunk in image.chunks(7) {
+        update.write(chunk)?;
+    }
+
+    let staged = update.finish(crc32(0, image))?;
+    assert_eq!(staged.size(), image.len());
+    Ok(staged.config().unwrap())
+}
+
+#[test]
+fn valid_image() {
+    let config = stage(
+        Update::with_flash(RamFlash::new(8)),
+        &image(TobootConfig::new().autorun()),
+    )
+    .unwrap();
+
+    assert!(config.autorun_enabled());
+    assert_eq!(u32::from(config.start_page()), layout::APP_START_PAGE);
+}
+
+#[test]
+fn lock_entry_refused() {
+    let image = image(TobootConfig::new().lock_entry_i_understand_this_locks_the_bootloader());
+
+    assert_eq!(
+        stage(Update::with_flash(RamFlash::new(8)), &image).err(),
+        Some(Error::LocksBootloader)
+    );
+
+    let update =
+        Update::with_flash(RamFlash::new(8)).lock_entry_i_understand_this_locks_the_bootloader();
+    assert!(stage(update, &image).unwrap().entry_locked());
+}
+
+#[test]
+fn crc_mismatch() {
+    let image = image(TobootConfig::new());
+
+    let mut update = Update::with_flash(RamFlash::new(8));
+    update.write(&image).unwrap();
+
+    assert_eq!(
+        update.finish(crc32(0, &image) ^ 1).err(),
+        Some(Error::CrcMismatch {
+            expected: crc32(0, &image) ^ 1,
+            actual: crc32(0, &image),
+        })
+    );
+}
+
+#[test]
+fn invalid_magic() {
+    let mut config = TobootConfig::new();
+    config.magic = 0x7000_0000;
+
+    assert_eq!(
+        stage(Update::with_flash(RamFlash::new(8)), &image(config)).err(),
+        Some(Error::InvalidMagic)
+    );
+}
+
+#[test]
+fn erase_mask_toboot_sectors() {
+    let mut config = TobootConfig::new();
+    config.erase_mask_lo = 1 << 15;
+
+    assert_eq!(
+        stage(Update::with_flash(RamFlash::new(8)), &image(config)).err(),
+        Some(Error::InvalidEraseMask)
+    );
+}
+
+#[test]
+fn erase_mask_image_sectors() {
+    // Second page of the 2KiB image.
+    let config = TobootConfig::new().erase_sector(layout::APP_START_PAGE + 1);
+
+    assert_eq!(
+        stage(Update::with_flash(RamFlash::new(8)), &image(config)).err(),
+        Some(Error::InvalidEraseMask)
+    );
+}
+
+#[cfg(feature = "persistent-data")]
+#[test]
+fn erase_mask_persistent_data() {
+    let config = TobootConfig::new().erase_sector(layout::PERSISTENT_START / PAGE_SIZE);
+
+    assert_eq!(
+        stage(Update::with_flash(RamFlash::new(8)), &image(config)).err(),
+        Some(Error::InvalidEraseMask)
+    );
+}
+
+#[test]
+fn too_large() {
+    let mut update = Update::with_flash(RamFlash::new(8));
+
+    assert_eq!(
+        update.write(&vec![0; 8 * PAGE_SIZE as usize + 1]).err(),
+        Some(Error::TooLarge)
+    );
+}
@@ -0,0 +1,35 @@
+/* Toboot specific part of memory.x, build.rs appends this to the generated
+ * MEMORY layout unless `no-bootloader` feature is enabled. */
+
+EXTERN(CONFIG);
+
+SECTIONS
+{
+    .toboot :
+    {
+        KEEP(*(.rodata.CONFIG));
+    } > FLASH
+
+} INSERT AFTER .vector_table;
+
+_stext = ADDR(.toboot) + SIZEOF(.toboot);
+
+/* Sectors (1KiB each) taken by the application image, from __app_start__
+ * up to the end of .data's load image. toboot's erase masks set through
+ * `toboot_config!` (or `toboot_erase_masks!` for the const fn builder)
+ * must not touch these, or updating will erase the program that has just
+ * been written. */
+__app_sector_start__ = __app_start__ / 1024;
+__app_sector_end__ = (LOADADDR(.data) + SIZEOF(.data) + 1023) / 1024;
+__app_sector_mask_lo__ = ((1 << MIN(__app_sector_end__, 32)) - 1)
+                       & ~((1 << MIN(__app_sector_start__, 32)) - 1);
+__app_sector_mask_hi__ = __app_sector_end__ > 32
+                       ? ((1 << (__app_sector_end__ - 32)) - 1)
+                         & ~((1 << (MAX(__app_sector_start__, 32) - 32)) - 1)
+                       : 0;
+
+ASSERT(((DEFINED(__toboot_erase_mask_lo__) ? __toboot_erase_mask_lo__ : 0) & __app_sector_mask_lo__) == 0
+    && ((DEFINED(__toboot_erase_mask_hi__) ? __toboot_erase_mask_hi__ : 0) & __app_sector_mask_hi__) == 0,
+    "toboot erase mask overlaps with the application image, check `erase`, `erase_mask_lo`, and `erase_mask_hi` in toboot_config!, or erase_* in TobootConfig builder");
+
+/* vim: set ft=ld : */